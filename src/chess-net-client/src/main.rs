@@ -1,9 +1,9 @@
 use std::net::TcpStream;
 use std::sync::{ Arc, Mutex };
 
-use chess3d::Board;
+use chess3d::{ Board, Colors, Move };
 
-use cursive::views::{ EditView, TextView, Panel };
+use cursive::views::{ EditView, RadioGroup, TextView, Panel };
 use cursive::view::{ Nameable, Resizable };
 use cursive::Cursive;
 use cursive::Printer;
@@ -16,13 +16,13 @@ use cursive::theme::Color;
 use cursive::theme::BaseColor;
 use cursive::theme::ColorStyle;
 
-use chess3d_common::ServerMessage;
+use chess3d_common::{ BoardSync, ClientMessage, FramedTransport, MessageTransport, ProtocolError, Role, ServerEvent, SyncStatus, PROTOCOL_VERSION };
 
 struct OnlineGame {
     chess_board: Arc<Mutex<Board>>,
     cursor: Option<(isize, isize, isize)>,
-    current_moves: Option<Vec<chess3d::Move>>,
-    stream: TcpStream,
+    current_moves: Option<Vec<Move>>,
+    connection: FramedTransport<TcpStream>,
 }
 
 impl OnlineGame {
@@ -31,12 +31,48 @@ impl OnlineGame {
             chess_board: board,
             cursor: None,
             current_moves: None,
-            stream: stream,
+            connection: FramedTransport::new(stream),
         }
     }
 }
 
+impl OnlineGame {
+    fn send_move(&mut self, m: &Move) {
+        if let Err(e) = self.connection.send(&ClientMessage::PlayerMove { r#move: *m }) {
+            println!("Could not send move: {}", e);
+        }
+    }
+
+    // asks the server for the whole board
+    fn request_resync(&mut self) {
+        if let Err(e) = self.connection.send(&ClientMessage::Resync) {
+            println!("Could not request resync: {}", e);
+        }
+    }
+}
+
+fn show_promotion_dialog(siv: &mut Cursive, candidates: &[Move]) {
+    let mut dialog = Dialog::text("Choose a piece").title("Promote Pawn");
+    for &m in candidates {
+        let label = match m.promotion() {
+            Some(p) => format!("{:?}", p),
+            None => continue,
+        };
+        dialog.add_button(label, move |s| {
+            s.pop_layer();
+            s.call_on_name("board", |v: &mut OnlineGame| v.send_move(&m));
+        });
+    }
+    siv.add_layer(dialog);
+}
+
+// the columns taken by one layer: two per file plus the frame and a gap
+fn layer_stride(files: usize) -> usize {
+    files * 2 + 3
+}
+
 impl cursive::view::View for OnlineGame {
+    #[allow(clippy::needless_range_loop)]
     fn draw(&self, printer: &Printer) {
         let mut bg_colors = [[[Color::Dark(BaseColor::White); 8]; 8]; 8];
 
@@ -66,11 +102,14 @@ impl cursive::view::View for OnlineGame {
             },
         };
         
-        for z in 0..8 {
-            printer.print_box((19 * z + 1 as usize, 1 as usize), (18, 10), false);
+        let dims = self.chess_board.as_ref().lock().unwrap().dimensions();
+        let (files, ranks) = (dims.files() as usize, dims.ranks() as usize);
+        let stride = layer_stride(files);
+        for z in 0..dims.layers() as usize {
+            printer.print_box((stride * z + 1, 1), (stride - 1, ranks + 2), false);
 
-            for y in (0..8).rev() {
-                for x in 0..8 {
+            for y in (0..ranks).rev() {
+                for x in 0..files {
                     let tile = {
                         // self.chess_board.borrow().at((x as isize, y as isize, z as isize))
                         self.chess_board.as_ref().lock().unwrap().at((x as isize, y as isize, z as isize))
@@ -79,21 +118,20 @@ impl cursive::view::View for OnlineGame {
                     use chess3d::BoardState;
                     use chess3d::Colors;
                     let mut text: String = " ".to_owned();
-                    let color;
-                    match tile {
+                    let color = match tile {
                         BoardState::Empty => { 
-                            text.push_str(" ");
-                            color = Color::Dark(BaseColor::White);
+                            text.push(' ');
+                            Color::Dark(BaseColor::White)
                         },
                         BoardState::Piece((Colors::Black, p)) => {
                             text.push(p.character());
-                            color = Color::RgbLowRes(0, 0, 0);
+                            Color::RgbLowRes(0, 0, 0)
                         },
                         BoardState::Piece((Colors::White, p)) => {
                             text.push(p.character());
-                            color = Color::Rgb(255, 255, 255);
+                            Color::Rgb(255, 255, 255)
                         },
-                    }
+                    };
                     // let bg_color = match self.cursor {
                     //     None => Color::Dark(BaseColor::White),
                     //     Some(_) => {
@@ -103,7 +141,7 @@ impl cursive::view::View for OnlineGame {
 
                     printer.with_color(
                         ColorStyle::new(color, bg_colors[x][y][z]),
-                        |printer| printer.print(((x * 2) + (19 * z + 2), (7 - y) + 2), &text)
+                        |printer| printer.print(((x * 2) + (stride * z + 2), (ranks - 1 - y) + 2), &text)
                     );
                 }
             }
@@ -111,9 +149,10 @@ impl cursive::view::View for OnlineGame {
     }
 
     fn required_size(&mut self, _: Vec2) -> Vec2 {
+        let dims = self.chess_board.as_ref().lock().unwrap().dimensions();
         Vec2 {
-            x: (19)*8 + 1,
-            y: 12,
+            x: layer_stride(dims.files() as usize) * dims.layers() as usize + 1,
+            y: dims.ranks() as usize + 4,
         }
     }
 
@@ -124,27 +163,35 @@ impl cursive::view::View for OnlineGame {
                 let mut pos: XY<isize> = (position.x as isize, position.y as isize).into();
                 let off: XY<isize> = (offset.x as isize, offset.y as isize).into();
                 pos = pos - off;
-                let board = (pos.x - 2) / 19;
-                pos.x -= 19 * board;
+                let dims = self.chess_board.as_ref().lock().unwrap().dimensions();
+                let stride = layer_stride(dims.files() as usize) as isize;
+                let board = (pos.x - 2) / stride;
+                pos.x -= stride * board;
                 pos = pos - (3, 2);
                 pos.x = (pos.x + 1) / 2;
-                pos.y = 7 - pos.y;
-                if pos.x >= 0 && pos.x <= 7 && pos.y >= 0 && pos.y <= 7 {
+                pos.y = dims.ranks() - 1 - pos.y;
+                if dims.contains((pos.x, pos.y, board).into()) {
                     // valid position
                     if let Some(moves) = &self.current_moves {
-                        let m = moves.iter().find(|i| i.to() == (pos.x, pos.y, board).into());
-                        if let Some(selected_move) = m {
-                            chess3d_common::emit_message(&mut self.stream, &ServerMessage::PlayerMove { r#move: selected_move.clone() });
-
+                        let candidates: Vec<Move> = moves.iter()
+                            .filter(|i| i.to() == (pos.x, pos.y, board).into())
+                            .cloned()
+                            .collect();
+                        if !candidates.is_empty() {
                             self.cursor = None;
                             self.current_moves = None;
+                            if candidates.len() > 1 {
+                                // only promotions share a destination square
+                                return EventResult::with_cb(move |s| show_promotion_dialog(s, &candidates));
+                            }
+                            self.send_move(&candidates[0]);
                         } else {
                             self.cursor = Some((pos.x, pos.y, board));
-                            self.current_moves = Some((*self.chess_board.as_ref()).lock().unwrap().piece_moves((pos.x, pos.y, board).into()));
+                            self.current_moves = Some((*self.chess_board.as_ref()).lock().unwrap().legal_piece_moves((pos.x, pos.y, board).into()));
                         }
                     } else {
                         self.cursor = Some((pos.x, pos.y, board));
-                        self.current_moves = Some((*self.chess_board.as_ref()).lock().unwrap().piece_moves((pos.x, pos.y, board).into()));
+                        self.current_moves = Some((*self.chess_board.as_ref()).lock().unwrap().legal_piece_moves((pos.x, pos.y, board).into()));
                     }
                     EventResult::Consumed(None)
                 } else {
@@ -185,7 +232,16 @@ fn main() {
     siv.run();
 }
 
+// sent to the server in Hello
+const CLIENT_NAME: &str = "chess-net-client";
+
 fn show_connect_dialog(siv: &mut Cursive) {
+    let mut role = RadioGroup::new();
+    let roles = LinearLayout::vertical()
+        .child(role.button(Role::Player { preferred: None }, "Play either colour"))
+        .child(role.button(Role::Player { preferred: Some(Colors::White) }, "Play White"))
+        .child(role.button(Role::Player { preferred: Some(Colors::Black) }, "Play Black"))
+        .child(role.button(Role::Spectator, "Watch"));
     siv.add_layer(
         Dialog::new()
             .title("Connect to Chess Match")
@@ -193,53 +249,138 @@ fn show_connect_dialog(siv: &mut Cursive) {
                 LinearLayout::vertical()
                     .child(TextView::new("Address"))
                     .child(EditView::new()
-                        .on_submit(connect_to_game)
+                        .on_submit(move |s, server| connect_to_game(s, server, *role.selection()))
                         .with_name("Address")
                         .fixed_width(20),
                     )
-                
+                    .child(roles)
             )
     );
 }
 
-fn connect_to_game(siv: &mut Cursive, server: &str) {
-    let stream = TcpStream::connect(server).unwrap();
+fn connect_to_game(siv: &mut Cursive, server: &str, role: Role) {
+    let stream = match TcpStream::connect(server) {
+        Ok(stream) => stream,
+        Err(e) => {
+            siv.add_layer(Dialog::info(format!("Could not connect to {}: {}", server, e)));
+            return;
+        }
+    };
+    let mut read_connection = FramedTransport::new(stream.try_clone().unwrap());
+    let hello = ClientMessage::Hello {
+        version: PROTOCOL_VERSION,
+        client: CLIENT_NAME.to_owned(),
+        role,
+    };
+    // a server from before the handshake ignores Hello and sends the board
+    // straight away, which is kept for the receiver thread
+    let mut pending = None;
+    let title = match read_connection.send(&hello).and_then(|_| read_connection.recv::<ServerEvent>()) {
+        Ok(ServerEvent::Welcome { color, game_id, .. }) => match color {
+            Some(c) => format!("Chess - game {} - playing {:?}", game_id, c),
+            None => format!("Chess - game {} - watching", game_id),
+        },
+        Ok(ServerEvent::Refused { reason }) => {
+            siv.add_layer(Dialog::info(format!("The server refused to let you join: {}", reason)));
+            return;
+        },
+        Ok(event) => {
+            pending = Some(event);
+            "Chess".to_owned()
+        },
+        Err(e) => {
+            siv.add_layer(Dialog::info(format!("Could not join {}: {}", server, e)));
+            return;
+        },
+    };
+
     let game_board = Arc::new(Mutex::new(Board::new()));
-    let board_view = OnlineGame::new(game_board.clone(), stream.try_clone().unwrap());
+    let board_view = OnlineGame::new(game_board.clone(), stream);
     siv.pop_layer().unwrap();
     siv.add_layer(
         Dialog::new()
-            .title("Chess")
+            .title(title)
             .content(
-                Panel::new(board_view)
+                Panel::new(board_view.with_name("board"))
             )
     );
 
     // start receiver thread
     use std::thread;
-    let mut read_stream = stream.try_clone().unwrap();
     let game_ref = game_board.clone();
 
     let user_data = siv.user_data::<CursiveData>().unwrap();
     let sink = user_data.sink.clone();
 
+    // servers from before the handshake never send GameOver, so stop
+    // listening to them once the board shows the game is over
+    let legacy = pending.is_some();
     thread::spawn(move || {
-        let mut running = { game_ref.as_ref().lock().unwrap().is_running() };
+        let mut running = true;
+        let mut sync = BoardSync::new();
         while running {
-            let data = chess3d_common::recv_message(&mut read_stream);
-            if let Ok(message) = data {
-                match message {
-                    ServerMessage::BoardUpdate { board } => {
-                        println!("Received board update");
-                        game_ref.as_ref().lock().unwrap().update_board(&board);
-                        sink.send(Box::new(|_| {})).unwrap();
-                        println!("Updated board successfully");
+            let event = match pending.take() {
+                Some(event) => Ok(event),
+                None => read_connection.recv::<ServerEvent>(),
+            };
+            match event {
+                Ok(event) => match event {
+                    ServerEvent::BoardUpdate { .. } | ServerEvent::MoveApplied { .. } => {
+                        let status = sync.apply(&mut game_ref.as_ref().lock().unwrap(), &event);
+                        match status {
+                            SyncStatus::Updated => sink.send(Box::new(|_| {})).unwrap(),
+                            SyncStatus::OutOfStep => {
+                                println!("Board out of step, asking for a resync");
+                                // sent from the UI thread, which writes everything else
+                                let _ = sink.send(Box::new(|s: &mut Cursive| {
+                                    s.call_on_name("board", |v: &mut OnlineGame| v.request_resync());
+                                }));
+                            },
+                            SyncStatus::Unchanged => {},
+                        }
                     },
-                    _ => {}
-                }
-
+                    ServerEvent::MoveRejected { reason } => {
+                        show_info(&sink, format!("Move rejected: {}", reason));
+                    },
+                    ServerEvent::GameOver { result } => {
+                        show_info(&sink, result.to_string());
+                        running = false;
+                    },
+                    ServerEvent::PlayerJoined { color: Some(c), client } => {
+                        show_info(&sink, format!("{} joined as {:?}", client, c));
+                    },
+                    ServerEvent::PlayerLeft { color: Some(c), client } => {
+                        show_info(&sink, format!("{} ({:?}) left the game", client, c));
+                    },
+                    ServerEvent::Error { message } => {
+                        show_info(&sink, format!("The server reported an error: {}", message));
+                    },
+                    // the move comes back in MoveApplied or BoardUpdate; spectators
+                    // come and go quietly; the rest only come in the handshake
+                    ServerEvent::MoveAccepted { .. }
+                        | ServerEvent::PlayerJoined { color: None, .. }
+                        | ServerEvent::PlayerLeft { color: None, .. }
+                        | ServerEvent::Welcome { .. }
+                        | ServerEvent::Refused { .. } => {},
+                },
+                // the frame was whole, so the next one can still be read
+                Err(e @ ProtocolError::Malformed { .. }) => println!("Ignoring message: {}", e),
+                Err(e) => {
+                    let _ = sink.send(Box::new(move |s: &mut Cursive| {
+                        s.add_layer(Dialog::info(format!("Lost connection: {}", e)));
+                    }));
+                    break;
+                },
+            }
+            if legacy {
                 running = game_ref.as_ref().lock().unwrap().is_running();
             }
         }
     });
-}
\ No newline at end of file
+}
+
+fn show_info(sink: &cursive::CbSink, text: String) {
+    let _ = sink.send(Box::new(move |s: &mut Cursive| {
+        s.add_layer(Dialog::info(text));
+    }));
+}
@@ -1,43 +1,212 @@
+use std::fs::OpenOptions;
+use std::io::Write;
 use std::net::{ TcpListener, TcpStream };
 use std::sync::{Arc, Mutex};
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
 
-use chess3d::Board;
-use chess3d_common::ServerMessage;
+use chess3d::{ Board, Colors, Dimensions, Game, GameRecord, Move, Notation };
+use chess3d_common::{ capabilities, ClientMessage, FramedTransport, MessageTransport, ProtocolError, Role, ServerEvent };
+use chess3d_common::{ MIN_PROTOCOL_VERSION, PROTOCOL_VERSION };
 
 struct Player {
-    con: TcpStream,
+    con: FramedTransport<TcpStream>,
     id: usize,
+    // None for spectators
+    color: Option<Colors>,
+    // the protocol version agreed in the handshake
+    version: u32,
 }
 
 impl Player {
-    fn new(stream: TcpStream, id: usize) -> Player {
+    fn new(stream: TcpStream, id: usize, color: Option<Colors>, version: u32) -> Player {
         Player {
-            con: stream,
-            id: id,
+            con: FramedTransport::new(stream),
+            id,
+            color,
+            version,
+        }
+    }
+
+    // sends the event if the player's protocol version has it
+    fn send(&mut self, event: &ServerEvent) {
+        if event.since_version() > self.version {
+            return;
+        }
+        if let Err(e) = self.con.send(event) {
+            println!("Could not send to player {}: {}", self.id, e);
         }
     }
 }
 
 struct ServerState {
-    board: Board,
-    players: Vec<Player>
+    game: Game,
+    game_id: u64,
+    players: Vec<Player>,
+    next_id: usize,
 }
 
+// finished games are appended here
+const ARCHIVE_PATH: &str = "games.pgn";
+// how long a new connection has to say Hello before it is taken for a client
+// from before the handshake
+const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+
 impl ServerState {
-    fn broadcast_all(&mut self, message: &ServerMessage) {
+    fn broadcast_all(&mut self, event: &ServerEvent) {
         for player in &mut self.players {
-            println!("Sent message");
-            chess3d_common::emit_message(&mut player.con, message);
+            player.send(event);
+        }
+    }
+
+    // all writes go through here, under the lock, so frames from different
+    // threads never interleave
+    fn send_to(&mut self, id: usize, event: &ServerEvent) {
+        if let Some(player) = self.players.iter_mut().find(|p| p.id == id) {
+            player.send(event);
+        }
+    }
+
+    fn broadcast_others(&mut self, id: usize, event: &ServerEvent) {
+        for player in self.players.iter_mut().filter(|p| p.id != id) {
+            player.send(event);
+        }
+    }
+
+    fn seat_taken(&self, c: Colors) -> bool {
+        self.players.iter().any(|p| p.color == Some(c))
+    }
+
+    // the preferred colour if it's free, otherwise whichever is
+    fn free_seat(&self, preferred: Option<Colors>) -> Option<Colors> {
+        let order = match preferred {
+            Some(c) => [c, c.opponent()],
+            None => [Colors::White, Colors::Black],
+        };
+        order.iter().copied().find(|&c| !self.seat_taken(c))
+    }
+
+    // seats a client that said Hello as it asked, or says why it can't be
+    fn seat_for(&self, role: Role) -> Result<Option<Colors>, String> {
+        match role {
+            Role::Player { preferred } => match self.free_seat(preferred) {
+                Some(c) => Ok(Some(c)),
+                None => Err("the game already has two players; join as a spectator to watch".to_owned()),
+            },
+            Role::Spectator => Ok(None),
+        }
+    }
+
+    // plays a move from a player of colour `color` if it is theirs to make
+    // and legal, otherwise says why not
+    fn play_move(&mut self, color: Option<Colors>, m: &Move) -> Result<(), String> {
+        let color = match color {
+            Some(c) => c,
+            None => return Err("spectators cannot move".to_owned()),
+        };
+        if color != self.game.board().side_to_move() {
+            return Err("it is not your turn".to_owned());
+        }
+        // checks the move against the side to move's legal moves, including
+        // that its piece matches the one on the board
+        let before = *self.game.board();
+        self.game.play(m).map_err(|e| e.to_string())?;
+        println!("Executing move: {}", before.format_move(m, Notation::Long));
+        if let Some(result) = self.game.result() {
+            println!("Game over: {:?}", result);
+            self.archive_game();
+        }
+        Ok(())
+    }
+
+    // tells everyone about a move just played, and the result if it ended the
+    // game; clients too old for MoveApplied get the whole board instead
+    fn announce_move(&mut self, m: Move) {
+        let applied = ServerEvent::MoveApplied {
+            r#move: m,
+            ply: self.game.moves().len() as u32,
+            hash: self.game.board().hash(),
+        };
+        let update = ServerEvent::BoardUpdate { board: *self.game.board() };
+        for player in &mut self.players {
+            if applied.since_version() <= player.version {
+                player.send(&applied);
+            } else {
+                player.send(&update);
+            }
+        }
+        if let Some(result) = self.game.result() {
+            self.broadcast_all(&ServerEvent::GameOver { result });
+        }
+    }
+
+    fn archive_game(&self) {
+        let mut record = GameRecord::from_game(&self.game);
+        record.set_tag("Event", "chess-server game");
+        record.set_tag("Date", &today());
+        for &(color, tag) in [(Colors::White, "White"), (Colors::Black, "Black")].iter() {
+            let address = self.players.iter()
+                .find(|p| p.color == Some(color))
+                .and_then(|p| p.con.get_ref().peer_addr().ok());
+            if let Some(address) = address {
+                record.set_tag(tag, &address.to_string());
+            }
+        }
+        let text = match record.to_pgn() {
+            Ok(text) => text,
+            Err(e) => {
+                println!("Could not archive game: {}", e);
+                return;
+            }
+        };
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(ARCHIVE_PATH)
+            .and_then(|mut file| writeln!(file, "{}", text));
+        match written {
+            Ok(()) => println!("Archived game to {}", ARCHIVE_PATH),
+            Err(e) => println!("Could not archive game: {}", e),
         }
     }
 }
 
+// today's UTC date as a record's Date tag, like 2021.01.31
+fn today() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    // days since 1970-01-01 to a civil date, counting years from March
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
 fn main() {
+    let board = match std::env::args().nth(1) {
+        Some(name) => match Board::preset(&name) {
+            Some(board) => board,
+            None => {
+                let names: Vec<&str> = Dimensions::PRESETS.iter().map(|&(n, _)| n).collect();
+                eprintln!("Unknown board size {}, expected one of: {}", name, names.join(", "));
+                std::process::exit(1);
+            }
+        },
+        None => Board::new(),
+    };
+
     let listener = TcpListener::bind("0.0.0.0:7878").unwrap();
 
+    let game_id = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
     let state = Arc::new(Mutex::new(ServerState {
-        board: Board::new(),
-        players: Vec::new()
+        game: Game::from_board(board),
+        game_id,
+        players: Vec::new(),
+        next_id: 0,
     }));
 
     for stream in listener.incoming() {
@@ -47,57 +216,167 @@ fn main() {
     }
 }
 
+// what a new connection opened with
+enum Greeting {
+    Hello { version: u32, client: String, role: Role },
+    // a client from before the handshake, and the message it sent first if any
+    Legacy(Option<ClientMessage>),
+}
+
+// the name given to clients from before the handshake
+const LEGACY_CLIENT: &str = "unnamed client";
+// the version those clients speak
+const LEGACY_VERSION: u32 = 1;
+
+// only the wait for the first byte is timed: a timeout part way through a
+// frame would lose the bytes already read and leave the stream out of step
+fn read_greeting(con: &mut FramedTransport<TcpStream>) -> Result<Greeting, ProtocolError> {
+    con.get_ref().set_read_timeout(Some(HELLO_TIMEOUT))?;
+    let waited = con.get_ref().peek(&mut [0; 1]);
+    con.get_ref().set_read_timeout(None)?;
+    match waited {
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {
+            return Ok(Greeting::Legacy(None));
+        },
+        Err(e) => return Err(e.into()),
+        Ok(_) => (),
+    }
+    match con.recv::<ClientMessage>()? {
+        ClientMessage::Hello { version, client, role } => Ok(Greeting::Hello { version, client, role }),
+        message => Ok(Greeting::Legacy(Some(message))),
+    }
+}
+
 fn handle_connection(s: TcpStream, state: Arc<Mutex<ServerState>>) {
     println!("Connection Received");
     use std::thread;
-    let stream = s.try_clone().unwrap();
-    let id = { state.lock().unwrap().players.len() };
-    { state.lock().unwrap().players.push(Player::new(s, id)) };
+    let stream = match s.try_clone() {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("Could not set up connection: {}", e);
+            return;
+        }
+    };
+    let id = {
+        let s = &mut *state.lock().unwrap();
+        s.next_id += 1;
+        s.next_id - 1
+    };
 
-    let mut player = Player::new(stream, id);
+    let mut con = FramedTransport::new(stream);
     thread::spawn(move || {
-        chess3d_common::emit_message(&mut player.con, &ServerMessage::BoardUpdate {
-            board: state.lock().unwrap().board,
-        });
-    
-        let mut running = { state.lock().unwrap().board.is_running() };
-        while running {
-            let data = chess3d_common::recv_message(&mut player.con);
-            if let Ok(message) = data {
-                match message {
-                    ServerMessage::PlayerMove { r#move } => {
-                        println!("PlayerMove {}", player.id);
-                        let s = &mut *state.lock().unwrap();
-                        // s.execute_move(&r#move);
-                        let piece = {
-                            state.lock().unwrap().board.at(r#move.from())
-                        };
-                        match piece {
-                            chess3d::BoardState::Piece((chess3d::Colors::White, _)) => {
-                                if player.id == 0 {
-                                    s.board.execute_move(&r#move);
-                                } else {
-                                    println!("Failed to move white piece");
-                                }
-                            },
-                            chess3d::BoardState::Piece((chess3d::Colors::Black, _)) => {
-                                if player.id == 1 {
-                                    s.board.execute_move(&r#move);
-                                    println!("Failed to move black piece");
-                                }
-                            },
-                            _ => { println!("Move denied"); }
-                        }
-                        println!("Executing move: {:?}", r#move);
-                        s.broadcast_all(&ServerMessage::BoardUpdate { board: s.board });
-                    },
-                    _ => {}
+        let greeting = match read_greeting(&mut con) {
+            Ok(greeting) => greeting,
+            Err(e) => {
+                println!("Lost player {} before it said hello: {}", id, e);
+                return;
+            }
+        };
+
+        let mut pending = None;
+        let (color, client) = {
+            let st = &mut *state.lock().unwrap();
+            let (seat, client, version) = match greeting {
+                Greeting::Hello { version, client, role } => {
+                    println!("Player {} is {} speaking version {}, asking to join as {:?}", id, client, version, role);
+                    let seat = if version < MIN_PROTOCOL_VERSION {
+                        Err(format!(
+                            "protocol version {} is not supported; this server speaks versions {} to {}",
+                            version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+                        ))
+                    } else {
+                        st.seat_for(role)
+                    };
+                    let reply = match &seat {
+                        Ok(color) => ServerEvent::Welcome {
+                            version: version.min(PROTOCOL_VERSION),
+                            color: *color,
+                            game_id: st.game_id,
+                            capabilities: vec![capabilities::MOVE_VALIDATION.to_owned(), capabilities::SPECTATORS.to_owned()],
+                        },
+                        Err(reason) => ServerEvent::Refused { reason: reason.clone() },
+                    };
+                    if let Err(e) = con.send(&reply) {
+                        println!("Could not send to player {}: {}", id, e);
+                        return;
+                    }
+                    (seat, client, version.min(PROTOCOL_VERSION))
+                },
+                Greeting::Legacy(first) => {
+                    pending = first;
+                    (Ok(st.free_seat(None)), LEGACY_CLIENT.to_owned(), LEGACY_VERSION)
+                },
+            };
+            let color = match seat {
+                Ok(color) => color,
+                Err(reason) => {
+                    println!("Refused player {}: {}", id, reason);
+                    return;
+                },
+            };
+            match con.get_ref().try_clone() {
+                Ok(stream) => st.players.push(Player::new(stream, id, color, version)),
+                Err(e) => {
+                    println!("Could not set up connection: {}", e);
+                    return;
                 }
-            } else {
-                break;
             }
-            running = state.lock().unwrap().board.is_running();
+            st.broadcast_others(id, &ServerEvent::PlayerJoined { color, client: client.clone() });
+            let board = *st.game.board();
+            st.send_to(id, &ServerEvent::BoardUpdate { board });
+            (color, client)
+        };
+        println!("Player {} joined as {:?}", id, color);
+
+        let mut running = state.lock().unwrap().game.board().is_running();
+        while running {
+            let message = match pending.take() {
+                Some(message) => Ok(message),
+                None => con.recv::<ClientMessage>(),
+            };
+            match message {
+                Ok(ClientMessage::PlayerMove { r#move }) => {
+                    println!("PlayerMove {}", id);
+                    let s = &mut *state.lock().unwrap();
+                    match s.play_move(color, &r#move) {
+                        Ok(()) => {
+                            s.send_to(id, &ServerEvent::MoveAccepted { r#move });
+                            s.announce_move(r#move);
+                        },
+                        Err(reason) => {
+                            println!("Move denied: {}", reason);
+                            s.send_to(id, &ServerEvent::MoveRejected { reason });
+                        },
+                    }
+                },
+                Ok(ClientMessage::Resync) => {
+                    println!("Resync {}", id);
+                    let s = &mut *state.lock().unwrap();
+                    let board = *s.game.board();
+                    s.send_to(id, &ServerEvent::BoardUpdate { board });
+                },
+                Ok(ClientMessage::Hello { .. }) => {
+                    let message = "Hello is only sent once, on connecting".to_owned();
+                    state.lock().unwrap().send_to(id, &ServerEvent::Error { message });
+                },
+                // the frame was whole, so the next one can still be read
+                Err(e @ ProtocolError::Malformed { .. }) => {
+                    println!("Ignoring message from player {}: {}", id, e);
+                    let message = format!("could not read your message: {}", e);
+                    state.lock().unwrap().send_to(id, &ServerEvent::Error { message });
+                },
+                Err(e) => {
+                    println!("Lost player {}: {}", id, e);
+                    break;
+                },
+            }
+            running = state.lock().unwrap().game.board().is_running();
         }
+
+        let s = &mut *state.lock().unwrap();
+        // frees the seat for someone else
+        s.players.retain(|p| p.id != id);
+        s.broadcast_all(&ServerEvent::PlayerLeft { color, client });
         println!("Ended connection");
     });
 
@@ -1,35 +1,235 @@
-use chess3d::{ Board, Move };
+use chess3d::{ Board, Colors, GameResult, Move };
+use serde::de::DeserializeOwned;
 use serde::{ Serialize, Deserialize };
-use std::net::TcpStream;
-use serde_json;
+use std::fmt;
+use std::io;
 use std::io::prelude::*;
 
-#[derive(Serialize, Deserialize)]
-pub enum ServerMessage {
+mod sync;
+mod transport;
+
+pub use sync::{ BoardSync, SyncStatus };
+pub use transport::{ duplex, DuplexStream, FramedTransport, MessageTransport };
+
+/// The protocol version this crate speaks, sent in `Hello`. Each change to
+/// the messages increases it.
+pub const PROTOCOL_VERSION: u32 = 3;
+/// The oldest version a server built from this crate still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Names for what a server supports, listed in `Welcome` so clients can tell
+/// which messages to expect.
+pub mod capabilities {
+    // every move is checked, and refused ones are answered with MoveRejected
+    pub const MOVE_VALIDATION: &str = "move-validation";
+    // clients may join to watch
+    pub const SPECTATORS: &str = "spectators";
+}
+
+/// What a client asks to join as.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Role {
+    // None takes whichever colour is free
+    Player { preferred: Option<Colors> },
+    Spectator,
+}
+
+/// Messages from a client to the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    // the first message from a client; clients from before the handshake
+    // existed skip it and are seated as they connect
+    Hello {
+        version: u32,
+        client: String,
+        role: Role,
+    },
+    PlayerMove {
+        r#move: Move,
+    },
+    // asks for a BoardUpdate after the client's board stopped matching the
+    // hashes in MoveApplied
+    Resync,
+}
+
+/// Messages from the server to its clients.
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum ServerEvent {
+    // the server's reply to an accepted Hello, followed by a BoardUpdate
+    Welcome {
+        // the version both sides will speak, no newer than the client's
+        version: u32,
+        // None for spectators
+        color: Option<Colors>,
+        game_id: u64,
+        capabilities: Vec<String>,
+    },
+    // the server's reply to a Hello it won't accept; it then disconnects
+    Refused {
+        reason: String,
+    },
+    // the whole position, sent on joining, in answer to Resync, and after
+    // every move to clients that don't know MoveApplied
     BoardUpdate {
         board: Board,
     },
-    PlayerMove {
+    // a move played by either side, for clients to play on their own board;
+    // ply counts the moves played so far including this one, and hash is the
+    // board's hash after it
+    MoveApplied {
+        r#move: Move,
+        ply: u32,
+        hash: u64,
+    },
+    // sent only to the player whose move was played
+    MoveAccepted {
         r#move: Move,
+    },
+    // sent only to the player whose move was refused
+    MoveRejected {
+        reason: String,
+    },
+    GameOver {
+        result: GameResult,
+    },
+    // someone else joined or left; color is None for spectators
+    PlayerJoined {
+        color: Option<Colors>,
+        client: String,
+    },
+    PlayerLeft {
+        color: Option<Colors>,
+        client: String,
+    },
+    // a problem with something the client sent other than a move
+    Error {
+        message: String,
+    },
+}
+
+impl ServerEvent {
+    /// The protocol version that introduced the event. Servers don't send
+    /// events to clients that speak an older version.
+    pub fn since_version(&self) -> u32 {
+        match self {
+            ServerEvent::Welcome { .. }
+                | ServerEvent::Refused { .. }
+                | ServerEvent::BoardUpdate { .. }
+                | ServerEvent::MoveRejected { .. } => 1,
+            ServerEvent::MoveAccepted { .. }
+                | ServerEvent::GameOver { .. }
+                | ServerEvent::PlayerJoined { .. }
+                | ServerEvent::PlayerLeft { .. }
+                | ServerEvent::Error { .. } => 2,
+            ServerEvent::MoveApplied { .. } => 3,
+        }
+    }
+}
+
+/// The largest frame `recv_message` accepts, in bytes. A board update is
+/// about 5 KB.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1 << 20;
+
+// how much of an undecodable frame to quote in the error
+const CONTEXT_LENGTH: usize = 64;
+
+/// Why a message could not be sent or received. After anything but
+/// `Malformed` the stream is out of step and should be closed.
+#[derive(Debug)]
+pub enum ProtocolError {
+    Io(io::Error),
+    // the stream ended part way through a frame; `received` is 0 of 4 if it
+    // ended cleanly between frames
+    Truncated { expected: usize, received: usize },
+    // the frame's length is over the limit; nothing after the header was read
+    TooLarge { size: usize, max: usize },
+    // the frame arrived whole but isn't a valid message
+    Malformed { error: serde_json::Error, context: String },
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Io(e) => write!(f, "I/O error: {}", e),
+            ProtocolError::Truncated { expected, received } => {
+                write!(f, "stream ended after {} of {} bytes", received, expected)
+            },
+            ProtocolError::TooLarge { size, max } => {
+                write!(f, "frame of {} bytes is over the limit of {}", size, max)
+            },
+            ProtocolError::Malformed { error, context } => {
+                write!(f, "malformed message ({}) in frame starting {:?}", error, context)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtocolError::Io(e) => Some(e),
+            ProtocolError::Malformed { error, .. } => Some(error),
+            _ => None,
+        }
     }
 }
 
-pub fn emit_message(stream: &mut TcpStream, message: &ServerMessage) {
-    let data = serde_json::to_string(message).unwrap();
-    let len = data.as_bytes().len() as u32;
-    stream.write(&u32::to_be_bytes(len)).unwrap();
-    stream.write(data.as_bytes()).unwrap();
-    stream.flush().unwrap();
+impl From<io::Error> for ProtocolError {
+    fn from(e: io::Error) -> ProtocolError {
+        ProtocolError::Io(e)
+    }
+}
+
+/// Writes `message` as a frame: its length as a big-endian u32, then its JSON.
+pub fn emit_message<W: Write, M: Serialize>(stream: &mut W, message: &M) -> Result<(), ProtocolError> {
+    let data = serde_json::to_vec(message).map_err(|error| ProtocolError::Malformed {
+        error,
+        context: String::new(),
+    })?;
+    if data.len() > u32::MAX as usize {
+        return Err(ProtocolError::TooLarge { size: data.len(), max: u32::MAX as usize });
+    }
+    stream.write_all(&u32::to_be_bytes(data.len() as u32))?;
+    stream.write_all(&data)?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Reads one frame, as written by `emit_message`, of at most
+/// `DEFAULT_MAX_FRAME_SIZE` bytes.
+pub fn recv_message<M: DeserializeOwned, R: Read>(stream: &mut R) -> Result<M, ProtocolError> {
+    recv_message_limited(stream, DEFAULT_MAX_FRAME_SIZE)
 }
 
-pub fn recv_message(stream: &mut TcpStream) -> Result<ServerMessage, std::io::Error> {
+/// Reads one frame of at most `max_frame_size` bytes.
+pub fn recv_message_limited<M: DeserializeOwned, R: Read>(stream: &mut R, max_frame_size: usize) -> Result<M, ProtocolError> {
     let mut len_buffer = [0; 4];
-    stream.read(&mut len_buffer)?;
+    read_frame_part(stream, &mut len_buffer)?;
     let len = u32::from_be_bytes(len_buffer) as usize;
-    
-    let mut data: Vec<u8> = Vec::with_capacity(len as usize);
-    data.resize(len, 0);
-    stream.read_exact(&mut data).unwrap();
+    if len > max_frame_size {
+        return Err(ProtocolError::TooLarge { size: len, max: max_frame_size });
+    }
+
+    let mut data: Vec<u8> = vec![0; len];
+    read_frame_part(stream, &mut data)?;
+
+    serde_json::from_slice::<M>(&data).map_err(|error| ProtocolError::Malformed {
+        error,
+        context: String::from_utf8_lossy(&data[..len.min(CONTEXT_LENGTH)]).into_owned(),
+    })
+}
 
-    Ok(serde_json::from_slice::<ServerMessage>(&data).unwrap())
-}
\ No newline at end of file
+// fills `buffer`, however many reads it takes
+fn read_frame_part<R: Read>(stream: &mut R, buffer: &mut [u8]) -> Result<(), ProtocolError> {
+    let mut received = 0;
+    while received < buffer.len() {
+        match stream.read(&mut buffer[received..]) {
+            Ok(0) => return Err(ProtocolError::Truncated { expected: buffer.len(), received }),
+            Ok(n) => received += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+//! Keeping a client's copy of the board in step with the server's, from the
+//! whole boards in `BoardUpdate` and the single moves in `MoveApplied`.
+
+use chess3d::Board;
+
+use crate::ServerEvent;
+
+/// What applying an event did to the client's board.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncStatus {
+    /// The board changed and should be redrawn.
+    Updated,
+    /// The board no longer matches the server's. The client should send
+    /// `Resync` once; moves are ignored until the board comes back.
+    OutOfStep,
+    /// Nothing changed: the event carries no board, or a resync is awaited.
+    Unchanged,
+}
+
+/// Tracks the moves applied to a client's board since the last whole board.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BoardSync {
+    // the ply of the last move applied since the last whole board
+    last_ply: Option<u32>,
+    // a resync was asked for, so moves are ignored until the board comes
+    resyncing: bool,
+}
+
+impl BoardSync {
+    pub fn new() -> BoardSync {
+        BoardSync::default()
+    }
+
+    /// Applies `event` to `board`, the client's copy. A move is out of step if
+    /// a ply was skipped, if it is illegal here, or if the board's hash after
+    /// it differs from the server's.
+    pub fn apply(&mut self, board: &mut Board, event: &ServerEvent) -> SyncStatus {
+        match *event {
+            ServerEvent::BoardUpdate { board: ref update } => {
+                board.update_board(update);
+                self.last_ply = None;
+                self.resyncing = false;
+                SyncStatus::Updated
+            },
+            ServerEvent::MoveApplied { .. } if self.resyncing => SyncStatus::Unchanged,
+            ServerEvent::MoveApplied { r#move, ply, hash } => {
+                let in_step = self.last_ply.is_none_or(|last| ply == last + 1)
+                    && board.try_move(&r#move).is_ok()
+                    && board.hash() == hash;
+                if in_step {
+                    self.last_ply = Some(ply);
+                    SyncStatus::Updated
+                } else {
+                    self.resyncing = true;
+                    SyncStatus::OutOfStep
+                }
+            },
+            _ => SyncStatus::Unchanged,
+        }
+    }
+}
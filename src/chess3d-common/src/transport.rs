@@ -0,0 +1,105 @@
+//! Carrying messages over any byte stream: a trait for sending and receiving
+//! whole messages, the length-prefixed framing over `Read + Write` that
+//! implements it, and an in-process stream pair for tests.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{ self, Cursor, Read, Write };
+use std::sync::mpsc::{ channel, Receiver, Sender };
+
+use crate::{ emit_message, recv_message_limited, ProtocolError, DEFAULT_MAX_FRAME_SIZE };
+
+/// Sends and receives whole messages. Clients send `ClientMessage`s and
+/// receive `ServerEvent`s, and the server the other way round.
+pub trait MessageTransport {
+    fn send<M: Serialize>(&mut self, message: &M) -> Result<(), ProtocolError>;
+
+    /// Waits for the next message.
+    fn recv<M: DeserializeOwned>(&mut self) -> Result<M, ProtocolError>;
+}
+
+/// Messages framed as by `emit_message` over a TCP or Unix socket, a TLS
+/// session, a pipe or anything else that is `Read + Write`.
+pub struct FramedTransport<S> {
+    stream: S,
+    max_frame_size: usize,
+}
+
+impl<S: Read + Write> FramedTransport<S> {
+    pub fn new(stream: S) -> FramedTransport<S> {
+        FramedTransport::with_max_frame_size(stream, DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// A transport that refuses to receive frames over `max_frame_size` bytes.
+    pub fn with_max_frame_size(stream: S, max_frame_size: usize) -> FramedTransport<S> {
+        FramedTransport { stream, max_frame_size }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write> MessageTransport for FramedTransport<S> {
+    fn send<M: Serialize>(&mut self, message: &M) -> Result<(), ProtocolError> {
+        emit_message(&mut self.stream, message)
+    }
+
+    fn recv<M: DeserializeOwned>(&mut self) -> Result<M, ProtocolError> {
+        recv_message_limited(&mut self.stream, self.max_frame_size)
+    }
+}
+
+/// One end of an in-memory byte stream made by `duplex`. Reads wait for the
+/// other end to write, and see the end of the stream once it is dropped.
+pub struct DuplexStream {
+    incoming: Receiver<Vec<u8>>,
+    outgoing: Sender<Vec<u8>>,
+    // what's left of the last write received
+    pending: Cursor<Vec<u8>>,
+}
+
+/// Two connected streams: what is written to one is read from the other. The
+/// ends may be moved to different threads.
+pub fn duplex() -> (DuplexStream, DuplexStream) {
+    let (a_out, b_in) = channel();
+    let (b_out, a_in) = channel();
+    let end = |incoming, outgoing| DuplexStream { incoming, outgoing, pending: Cursor::new(Vec::new()) };
+    (end(a_in, a_out), end(b_in, b_out))
+}
+
+impl Read for DuplexStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.pending.position() as usize == self.pending.get_ref().len() {
+            match self.incoming.recv() {
+                Ok(data) => self.pending = Cursor::new(data),
+                // the other end is gone
+                Err(_) => return Ok(0),
+            }
+        }
+        self.pending.read(buf)
+    }
+}
+
+impl Write for DuplexStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the other end of the stream was dropped"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+//! Reading and writing length-prefixed frames over streams that deliver data in
+//! pieces, end early or carry garbage.
+
+use std::io::{ self, Cursor, Read };
+
+use chess3d::Board;
+use chess3d_common::{ emit_message, recv_message, recv_message_limited, ProtocolError, ServerEvent, DEFAULT_MAX_FRAME_SIZE };
+
+// hands out at most `chunk` bytes per read, interrupting every other read
+struct Fragmented {
+    data: Cursor<Vec<u8>>,
+    chunk: usize,
+    interrupt: bool,
+}
+
+impl Fragmented {
+    fn new(data: Vec<u8>, chunk: usize) -> Fragmented {
+        Fragmented { data: Cursor::new(data), chunk, interrupt: false }
+    }
+}
+
+impl Read for Fragmented {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+        }
+        let n = buf.len().min(self.chunk);
+        self.data.read(&mut buf[..n])
+    }
+}
+
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(payload);
+    data
+}
+
+fn encoded(messages: &[ServerEvent]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for m in messages {
+        emit_message(&mut data, m).unwrap();
+    }
+    data
+}
+
+fn assert_board(message: ServerEvent, expected: &Board) {
+    match message {
+        ServerEvent::BoardUpdate { board } => assert_eq!(board.to_fen(), expected.to_fen()),
+        _ => panic!("expected a board update"),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let board = Board::new();
+    let mut stream = Cursor::new(encoded(&[
+        ServerEvent::BoardUpdate { board },
+        ServerEvent::MoveRejected { reason: "it is not your turn".to_owned() },
+    ]));
+    assert_board(recv_message::<ServerEvent, _>(&mut stream).unwrap(), &board);
+    match recv_message::<ServerEvent, _>(&mut stream).unwrap() {
+        ServerEvent::MoveRejected { reason } => assert_eq!(reason, "it is not your turn"),
+        _ => panic!("expected a rejection"),
+    }
+}
+
+#[test]
+fn fragmented_reads() {
+    let board = Board::new();
+    let data = encoded(&[ServerEvent::BoardUpdate { board }, ServerEvent::BoardUpdate { board }]);
+    for &chunk in &[1, 3, 4, 5, 1000] {
+        let mut stream = Fragmented::new(data.clone(), chunk);
+        assert_board(recv_message::<ServerEvent, _>(&mut stream).unwrap(), &board);
+        assert_board(recv_message::<ServerEvent, _>(&mut stream).unwrap(), &board);
+        assert!(matches!(
+            recv_message::<ServerEvent, _>(&mut stream),
+            Err(ProtocolError::Truncated { expected: 4, received: 0 })
+        ));
+    }
+}
+
+#[test]
+fn truncated_header() {
+    let mut stream = Fragmented::new(vec![0, 0], 1);
+    assert!(matches!(
+        recv_message::<ServerEvent, _>(&mut stream),
+        Err(ProtocolError::Truncated { expected: 4, received: 2 })
+    ));
+}
+
+#[test]
+fn truncated_body() {
+    let mut data = encoded(&[ServerEvent::BoardUpdate { board: Board::new() }]);
+    let len = data.len() - 4;
+    data.truncate(100);
+    let mut stream = Fragmented::new(data, 7);
+    match recv_message::<ServerEvent, _>(&mut stream) {
+        Err(ProtocolError::Truncated { expected, received }) => {
+            assert_eq!(expected, len);
+            assert_eq!(received, 96);
+        },
+        _ => panic!("expected a truncated frame"),
+    }
+}
+
+#[test]
+fn frame_size_limit() {
+    let data = encoded(&[ServerEvent::BoardUpdate { board: Board::new() }]);
+    let len = data.len() - 4;
+    assert!(len < DEFAULT_MAX_FRAME_SIZE);
+
+    let mut stream = Cursor::new(data.clone());
+    match recv_message_limited::<ServerEvent, _>(&mut stream, len - 1) {
+        Err(ProtocolError::TooLarge { size, max }) => assert_eq!((size, max), (len, len - 1)),
+        _ => panic!("expected the frame to be too large"),
+    }
+    // the body is left unread
+    assert_eq!(stream.position(), 4);
+
+    let mut stream = Cursor::new(data);
+    assert!(recv_message_limited::<ServerEvent, _>(&mut stream, len).is_ok());
+
+    // a garbage length is refused before anything is allocated for it
+    let mut stream = Cursor::new(vec![0xff, 0xff, 0xff, 0xff, b'{']);
+    assert!(matches!(recv_message::<ServerEvent, _>(&mut stream), Err(ProtocolError::TooLarge { size: 0xffff_ffff, .. })));
+}
+
+#[test]
+fn malformed_json() {
+    let mut data = frame(b"{\"BoardUpdate\": 42}");
+    data.extend(frame(&[0xde, 0xad, 0xbe, 0xef]));
+    data.extend(frame(b""));
+    data.extend(encoded(&[ServerEvent::MoveRejected { reason: "still in step".to_owned() }]));
+    let mut stream = Fragmented::new(data, 2);
+
+    match recv_message::<ServerEvent, _>(&mut stream) {
+        Err(ProtocolError::Malformed { context, .. }) => assert_eq!(context, "{\"BoardUpdate\": 42}"),
+        _ => panic!("expected malformed JSON"),
+    }
+    assert!(matches!(recv_message::<ServerEvent, _>(&mut stream), Err(ProtocolError::Malformed { .. })));
+    assert!(matches!(recv_message::<ServerEvent, _>(&mut stream), Err(ProtocolError::Malformed { .. })));
+    // whole frames that fail to decode don't disturb the ones after them
+    assert!(matches!(recv_message::<ServerEvent, _>(&mut stream), Ok(ServerEvent::MoveRejected { .. })));
+}
+
+// a board update as JSON, to be tampered with
+fn board_json(board: Board) -> serde_json::Value {
+    serde_json::to_value(ServerEvent::BoardUpdate { board }).unwrap()
+}
+
+#[test]
+fn boards_with_squares_off_the_board() {
+    let mut board = Board::new();
+    let m = board.parse_move("e2A-e4A").unwrap();
+    board.try_move(&m).unwrap();
+    let mut far_target = board_json(board);
+    far_target["BoardUpdate"]["board"]["en_passant"]["target"]["x"] = 100.into();
+
+    // the training board has four files, so the fifth is off it
+    let mut far_piece = board_json(Board::preset("training").unwrap());
+    let squares = &mut far_piece["BoardUpdate"]["board"]["board"];
+    squares[4][0][0] = squares[0][0][0].clone();
+
+    for json in &[far_target, far_piece] {
+        let mut stream = Cursor::new(frame(json.to_string().as_bytes()));
+        assert!(matches!(recv_message::<ServerEvent, _>(&mut stream), Err(ProtocolError::Malformed { .. })), "{}", json);
+    }
+    let mut stream = Cursor::new(frame(board_json(board).to_string().as_bytes()));
+    assert_board(recv_message(&mut stream).unwrap(), &board);
+}
+
+#[test]
+fn malformed_context_is_shortened() {
+    let payload = vec![b'x'; 1000];
+    let mut stream = Cursor::new(frame(&payload));
+    match recv_message::<ServerEvent, _>(&mut stream) {
+        Err(ProtocolError::Malformed { context, .. }) => assert!(context.len() < 100),
+        _ => panic!("expected malformed JSON"),
+    }
+}
+
+#[test]
+fn garbage_never_panics() {
+    // a simple generator so the test needs no dependencies
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    for _ in 0..500 {
+        let len = (next() % 64) as usize;
+        let mut data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+        // sometimes give it a plausible header
+        if next() % 2 == 0 && len >= 4 {
+            data[..4].copy_from_slice(&((len - 4) as u32).to_be_bytes());
+        }
+        let mut stream = Fragmented::new(data, 1 + (next() % 5) as usize);
+        while recv_message::<ServerEvent, _>(&mut stream).is_ok() {}
+    }
+}
+
+#[test]
+fn write_errors_are_returned() {
+    struct Broken;
+    impl io::Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let result = emit_message(&mut Broken, &ServerEvent::MoveRejected { reason: String::new() });
+    assert!(matches!(result, Err(ProtocolError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe));
+}
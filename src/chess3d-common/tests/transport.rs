@@ -0,0 +1,182 @@
+//! Messages over the in-process duplex stream, which stands in for a socket.
+
+use std::io::{ self, Read, Write };
+use std::thread;
+
+use chess3d::{ Board, Game };
+use chess3d_common::{ duplex, BoardSync, ClientMessage, FramedTransport, MessageTransport, ProtocolError, ServerEvent, SyncStatus };
+
+#[test]
+fn duplex_carries_bytes_both_ways() {
+    let (mut a, mut b) = duplex();
+    a.write_all(b"hello").unwrap();
+    b.write_all(b"hi").unwrap();
+
+    let mut buf = [0; 3];
+    b.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hel");
+    b.read_exact(&mut buf[..2]).unwrap();
+    assert_eq!(&buf[..2], b"lo");
+
+    let mut buf = Vec::new();
+    drop(b);
+    a.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, b"hi");
+    // nobody is left to read
+    assert_eq!(a.write(b"x").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn messages_between_threads() {
+    let (server, client) = duplex();
+    let mut server = FramedTransport::new(server);
+    let mut client = FramedTransport::new(client);
+
+    let board = Board::new();
+    let m = board.parse_move("e2A-e4A").unwrap();
+    let handle = thread::spawn(move || {
+        // answers each move with the board after it
+        while let Ok(ClientMessage::PlayerMove { r#move }) = server.recv() {
+            let mut board = Board::new();
+            board.try_move(&r#move).unwrap();
+            server.send(&ServerEvent::BoardUpdate { board }).unwrap();
+        }
+    });
+
+    for _ in 0..3 {
+        client.send(&ClientMessage::PlayerMove { r#move: m }).unwrap();
+        match client.recv().unwrap() {
+            ServerEvent::BoardUpdate { board } => assert_eq!(board.to_fen().split(' ').nth(1), Some("b")),
+            _ => panic!("expected a board update"),
+        }
+    }
+    drop(client);
+    handle.join().unwrap();
+}
+
+#[test]
+fn closed_peer_ends_the_stream() {
+    let (a, b) = duplex();
+    let mut a = FramedTransport::new(a);
+    a.send(&ServerEvent::MoveRejected { reason: "last words".to_owned() }).unwrap();
+    let mut b = FramedTransport::new(b);
+    drop(a);
+
+    assert!(matches!(b.recv(), Ok(ServerEvent::MoveRejected { .. })));
+    assert!(matches!(
+        b.recv::<ServerEvent>(),
+        Err(ProtocolError::Truncated { expected: 4, received: 0 })
+    ));
+    assert!(matches!(
+        b.send(&ServerEvent::MoveRejected { reason: String::new() }),
+        Err(ProtocolError::Io(_))
+    ));
+}
+
+#[test]
+fn max_frame_size() {
+    let (a, b) = duplex();
+    let mut a = FramedTransport::new(a);
+    let mut b = FramedTransport::with_max_frame_size(b, 100);
+
+    a.send(&ServerEvent::MoveRejected { reason: "short".to_owned() }).unwrap();
+    assert!(b.recv::<ServerEvent>().is_ok());
+
+    a.send(&ServerEvent::BoardUpdate { board: Board::new() }).unwrap();
+    assert!(matches!(b.recv::<ServerEvent>(), Err(ProtocolError::TooLarge { max: 100, .. })));
+}
+
+#[test]
+fn transports_mix_with_the_free_functions() {
+    let (a, b) = duplex();
+    let mut a = FramedTransport::new(a);
+    a.send(&ServerEvent::MoveRejected { reason: "framed".to_owned() }).unwrap();
+    chess3d_common::emit_message(a.get_mut(), &ServerEvent::MoveRejected { reason: "free".to_owned() }).unwrap();
+
+    let mut b = b;
+    for expected in &["framed", "free"] {
+        match chess3d_common::recv_message(&mut b) {
+            Ok(ServerEvent::MoveRejected { reason }) => assert_eq!(&reason, expected),
+            _ => panic!("expected a rejection"),
+        }
+    }
+}
+
+#[test]
+fn moves_applied_keep_a_copy_in_step() {
+    let (server, client) = duplex();
+    let mut server = FramedTransport::new(server);
+    let mut client = FramedTransport::new(client);
+
+    let handle = thread::spawn(move || {
+        let mut game = Game::new();
+        server.send(&ServerEvent::BoardUpdate { board: *game.board() }).unwrap();
+        for text in &["e2A-e4A", "e7H-e5H", "d2A-d4A", "d7H-d5H"] {
+            let m = game.board().parse_move(text).unwrap();
+            game.play(&m).unwrap();
+            let ply = game.moves().len() as u32;
+            // a wrong hash on the third move stands in for a diverged client
+            let hash = if ply == 3 { !game.board().hash() } else { game.board().hash() };
+            server.send(&ServerEvent::MoveApplied { r#move: m, ply, hash }).unwrap();
+        }
+        assert!(matches!(server.recv(), Ok(ClientMessage::Resync)));
+        server.send(&ServerEvent::BoardUpdate { board: *game.board() }).unwrap();
+        game.board().to_fen()
+    });
+
+    let mut board = Board::new();
+    let mut sync = BoardSync::new();
+    let mut statuses = Vec::new();
+    for _ in 0..5 {
+        let event = client.recv().unwrap();
+        let status = sync.apply(&mut board, &event);
+        if status == SyncStatus::OutOfStep {
+            client.send(&ClientMessage::Resync).unwrap();
+        }
+        statuses.push(status);
+    }
+    // the move after the diverged one is ignored while the board is awaited
+    assert_eq!(statuses, [
+        SyncStatus::Updated,
+        SyncStatus::Updated,
+        SyncStatus::Updated,
+        SyncStatus::OutOfStep,
+        SyncStatus::Unchanged,
+    ]);
+
+    let event = client.recv().unwrap();
+    assert_eq!(sync.apply(&mut board, &event), SyncStatus::Updated);
+    assert_eq!(board.to_fen(), handle.join().unwrap());
+}
+
+#[test]
+fn skipped_and_illegal_moves_are_out_of_step() {
+    // the server's moves, with the ply and hash after each
+    let mut game = Game::new();
+    let mut moves = Vec::new();
+    for text in &["e2A-e4A", "e7H-e5H"] {
+        let m = game.board().parse_move(text).unwrap();
+        game.play(&m).unwrap();
+        moves.push(ServerEvent::MoveApplied { r#move: m, ply: game.moves().len() as u32, hash: game.board().hash() });
+    }
+    let skipping = match moves[1] {
+        ServerEvent::MoveApplied { r#move, hash, .. } => ServerEvent::MoveApplied { r#move, ply: 3, hash },
+        _ => unreachable!(),
+    };
+
+    // the first move after a whole board may have any ply, but the next
+    // must follow it even when the move itself fits
+    let (mut board, mut sync) = (Board::new(), BoardSync::new());
+    assert_eq!(sync.apply(&mut board, &moves[0]), SyncStatus::Updated);
+    assert_eq!(sync.apply(&mut board, &skipping), SyncStatus::OutOfStep);
+
+    // a move that is illegal on the client's board
+    let (mut board, mut sync) = (Board::new(), BoardSync::new());
+    assert_eq!(sync.apply(&mut board, &moves[1]), SyncStatus::OutOfStep);
+
+    // events without a board leave it alone
+    let (mut board, mut sync) = (Board::new(), BoardSync::new());
+    let rejected = ServerEvent::MoveRejected { reason: String::new() };
+    assert_eq!(sync.apply(&mut board, &rejected), SyncStatus::Unchanged);
+    assert_eq!(board.to_fen(), Board::new().to_fen());
+}
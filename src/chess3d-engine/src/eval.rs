@@ -0,0 +1,105 @@
+//! Material and piece-square evaluation. The piece-square tables are built
+//! for the board's dimensions: pieces gain for standing near the centre of
+//! the board and pawns for their progress towards promotion.
+
+use chess3d::bitboard::{ kind_index, square_index, square_location, SQUARES };
+use chess3d::{ Board, Colors, Dimensions, Location, Pieces };
+
+/// Piece values in centipawns, indexed by `kind_index`.
+pub const PIECE_VALUES: [i32; 6] = [100, 320, 350, 500, 950, 0];
+
+// one piece of each kind, in `kind_index` order
+pub(crate) const KINDS: [Pieces; 6] = [
+    Pieces::Pawn(false), Pieces::Knight, Pieces::Bishop, Pieces::Rook(false), Pieces::Queen, Pieces::King(false),
+];
+
+// centipawns per step towards the centre, indexed by `kind_index`
+const CENTRALITY: [i32; 6] = [2, 8, 5, 2, 3, 0];
+// centipawns per step towards promotion
+const PAWN_PROGRESS: i32 = 10;
+// kings keep away from the centre while the opponent has pieces to attack
+// with, and head for it once they're gone
+const KING_SHELTER: i32 = -6;
+const KING_ACTIVITY: i32 = 6;
+// non-pawn material left below which kings become active
+const ENDGAME_MATERIAL: i32 = 1300;
+
+pub struct Evaluator {
+    dimensions: Dimensions,
+    // bonuses for White pieces by kind and square; Black uses the mirrored square
+    tables: Vec<[i32; SQUARES]>,
+    king_endgame: [i32; SQUARES],
+}
+
+impl Evaluator {
+    pub fn new(dimensions: Dimensions) -> Evaluator {
+        let (files, ranks, layers) = (dimensions.files(), dimensions.ranks(), dimensions.layers());
+        let centre = |n: isize, c: isize| {
+            // twice the distance from the middle, to stay in integers
+            let distance = (2 * c - (n - 1)).abs();
+            (n - 1) - distance
+        };
+        let mut tables = vec![[0; SQUARES]; 6];
+        let mut king_endgame = [0; SQUARES];
+        for x in 0..files {
+            for y in 0..ranks {
+                for z in 0..layers {
+                    let square = square_index(Location::new(x, y, z));
+                    // in half steps, from 0 on the corners upwards
+                    let centrality = (centre(files, x) + centre(ranks, y) + centre(layers, z)) as i32;
+                    for (kind, table) in tables.iter_mut().enumerate() {
+                        table[square] = CENTRALITY[kind] * centrality / 2;
+                    }
+                    tables[kind_index(Pieces::Pawn(false))][square] += PAWN_PROGRESS * (y + z) as i32;
+                    tables[kind_index(Pieces::King(false))][square] = KING_SHELTER * centrality / 2;
+                    king_endgame[square] = KING_ACTIVITY * centrality / 2;
+                }
+            }
+        }
+        Evaluator { dimensions, tables, king_endgame }
+    }
+
+    // White's view of `l`: Black's pieces are mirrored through the centre
+    fn square(&self, l: Location, c: Colors) -> usize {
+        match c {
+            Colors::White => square_index(l),
+            Colors::Black => square_index(Location::new(
+                l.x,
+                self.dimensions.ranks() - 1 - l.y,
+                self.dimensions.layers() - 1 - l.z,
+            )),
+        }
+    }
+
+    /// The position's score in centipawns for the side to move.
+    pub fn evaluate(&self, board: &Board) -> i32 {
+        let mut non_pawn = [0; 2];
+        for (i, &c) in [Colors::White, Colors::Black].iter().enumerate() {
+            for &p in &KINDS[1..5] {
+                non_pawn[i] += PIECE_VALUES[kind_index(p)] * board.piece_set(c, p).count() as i32;
+            }
+        }
+
+        let mut score = 0;
+        for (i, &c) in [Colors::White, Colors::Black].iter().enumerate() {
+            let mut side = 0;
+            for (kind, &p) in KINDS.iter().enumerate() {
+                let table = if kind == kind_index(Pieces::King(false)) && non_pawn[1 - i] < ENDGAME_MATERIAL {
+                    &self.king_endgame
+                } else {
+                    &self.tables[kind]
+                };
+                for square in board.piece_set(c, p).squares() {
+                    let l = square_location(square);
+                    side += PIECE_VALUES[kind] + table[self.square(l, c)];
+                }
+            }
+            score += if c == Colors::White { side } else { -side };
+        }
+
+        match board.side_to_move() {
+            Colors::White => score,
+            Colors::Black => -score,
+        }
+    }
+}
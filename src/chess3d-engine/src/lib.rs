@@ -0,0 +1,162 @@
+//! A computer player for `chess3d`: iterative-deepening alpha-beta search with
+//! a transposition table, quiescence search and MVV-LVA, killer and history
+//! move ordering over a material and piece-square evaluation.
+
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+use chess3d::{ Board, Move };
+
+mod eval;
+mod search;
+mod tt;
+
+pub use eval::{ Evaluator, PIECE_VALUES };
+
+use search::Search;
+use tt::TranspositionTable;
+
+/// The score of giving checkmate on the next move. Mate in `n` plies scores
+/// `MATE - n`, and being mated scores the negation.
+pub const MATE: i32 = 30_000;
+/// The deepest the search goes, counting extensions and quiescence.
+pub const MAX_PLY: usize = 64;
+
+const DEFAULT_HASH_MEGABYTES: usize = 16;
+
+/// When to stop searching. The search always completes depth 1; beyond that
+/// it stops at whichever limit it reaches first, or only when told to if
+/// there are none.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u32) -> SearchLimits {
+        SearchLimits { depth: Some(depth), ..SearchLimits::default() }
+    }
+
+    pub fn time(time: Duration) -> SearchLimits {
+        SearchLimits { time: Some(time), ..SearchLimits::default() }
+    }
+}
+
+/// The outcome of the deepest completed iteration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    // None if the side to move has no legal moves
+    pub best_move: Option<Move>,
+    // centipawns for the side to move; see `MATE`
+    pub score: i32,
+    pub depth: u32,
+    // the expected line of play, starting with the best move
+    pub pv: Vec<Move>,
+    // every position visited, including those in quiescence search
+    pub nodes: u64,
+    pub quiescence_nodes: u64,
+    pub elapsed: Duration,
+}
+
+impl SearchResult {
+    /// Moves until mate if the score is a forced mate: positive when the
+    /// side to move gives it and negative when it is mated.
+    pub fn mate_in(&self) -> Option<i32> {
+        let plies = MATE - self.score.abs();
+        if plies > MAX_PLY as i32 {
+            return None;
+        }
+        let moves = (plies + 1) / 2;
+        Some(if self.score > 0 { moves } else { -moves })
+    }
+}
+
+/// A searcher that keeps its transposition table between searches.
+pub struct Engine {
+    tt: TranspositionTable,
+    stop: Arc<AtomicBool>,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::with_hash_size(DEFAULT_HASH_MEGABYTES)
+    }
+
+    /// An engine with a transposition table of about `megabytes` megabytes.
+    pub fn with_hash_size(megabytes: usize) -> Engine {
+        Engine {
+            tt: TranspositionTable::new(megabytes),
+            stop: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Setting the flag ends the current search, which returns the result of
+    /// the last completed iteration. The search clears it before returning.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Forgets everything learnt from earlier searches.
+    pub fn clear(&mut self) {
+        self.tt.clear();
+    }
+
+    /// Searches `board` until a limit is reached, calling `on_iteration` with
+    /// the result of each completed depth.
+    pub fn search<F: FnMut(&SearchResult)>(&mut self, board: &Board, limits: SearchLimits, mut on_iteration: F) -> SearchResult {
+        let start = Instant::now();
+        let mut search = Search::new(board, &mut self.tt, &self.stop, limits, start);
+        let max_depth = limits.depth.unwrap_or(MAX_PLY as u32).clamp(1, MAX_PLY as u32 - 1);
+
+        let mut result = SearchResult {
+            best_move: None,
+            score: 0,
+            depth: 0,
+            pv: Vec::new(),
+            nodes: 0,
+            quiescence_nodes: 0,
+            elapsed: Duration::default(),
+        };
+        for depth in 1..=max_depth {
+            let score = search.root(depth);
+            // an unfinished iteration is thrown away; depth 1 always finishes
+            if search.stopped() {
+                break;
+            }
+            let pv = search.pv();
+            result = SearchResult {
+                best_move: pv.first().copied(),
+                score,
+                depth,
+                pv,
+                nodes: search.nodes,
+                quiescence_nodes: search.quiescence_nodes,
+                elapsed: start.elapsed(),
+            };
+            on_iteration(&result);
+            let mate_found = result.mate_in().is_some() && limits.depth.is_none();
+            if search.limit_reached() || result.best_move.is_none() || mate_found {
+                break;
+            }
+        }
+        result.nodes = search.nodes;
+        result.quiescence_nodes = search.quiescence_nodes;
+        result.elapsed = start.elapsed();
+        self.stop.store(false, Ordering::Relaxed);
+        result
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Engine {
+        Engine::new()
+    }
+}
+
+/// Searches `board` with a fresh engine and returns the best move found.
+pub fn best_move(board: &Board, limits: SearchLimits) -> SearchResult {
+    Engine::new().search(board, limits, |_| {})
+}
@@ -0,0 +1,242 @@
+//! The engine as a program that speaks a line protocol in the style of UCI on
+//! stdin and stdout, so that GUIs and scripts can drive it. PROTOCOL.md in
+//! this crate describes the commands.
+
+use std::io::{ self, BufRead };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::thread::{ self, JoinHandle };
+use std::time::Duration;
+
+use chess3d::{ Board, Colors, Notation };
+use chess3d_engine::{ Engine, SearchLimits, SearchResult };
+
+const NAME: &str = "chess3d-engine";
+const AUTHOR: &str = "Nolan K";
+
+const DEFAULT_HASH: usize = 16;
+const MAX_HASH: usize = 4096;
+// how many more moves to budget the clock for when the GUI doesn't say
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+// kept back from every move for the reply to reach the GUI
+const MOVE_OVERHEAD: u64 = 50;
+
+struct Session {
+    // None while a search has it
+    engine: Option<Engine>,
+    board: Board,
+    search: Option<RunningSearch>,
+}
+
+struct RunningSearch {
+    handle: JoinHandle<Engine>,
+    stop: Arc<AtomicBool>,
+    limits: SearchLimits,
+}
+
+impl Session {
+    fn new() -> Session {
+        Session {
+            engine: Some(Engine::with_hash_size(DEFAULT_HASH)),
+            board: Board::new(),
+            search: None,
+        }
+    }
+
+    // handles one line of input; returns false once told to quit
+    fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(c) => c,
+            None => return true,
+        };
+        let args: Vec<&str> = words.collect();
+        match command {
+            "uci" => {
+                println!("id name {}", NAME);
+                println!("id author {}", AUTHOR);
+                println!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH, MAX_HASH);
+                println!("option name Clear Hash type button");
+                println!("uciok");
+            },
+            "isready" => println!("readyok"),
+            "ucinewgame" => {
+                self.settle_search();
+                self.engine().clear();
+            },
+            "setoption" => {
+                self.settle_search();
+                self.set_option(&args);
+            },
+            "position" => {
+                self.settle_search();
+                match parse_position(&args) {
+                    Ok(board) => self.board = board,
+                    Err(e) => println!("info string {}", e),
+                }
+            },
+            "go" => {
+                self.settle_search();
+                match parse_go(&args, &self.board) {
+                    Ok(limits) => self.go(limits),
+                    Err(e) => println!("info string {}", e),
+                }
+            },
+            "stop" => self.finish_search(),
+            "quit" => {
+                self.finish_search();
+                return false;
+            },
+            _ => println!("info string unknown command: {}", command),
+        }
+        true
+    }
+
+    fn engine(&mut self) -> &mut Engine {
+        self.engine.as_mut().expect("engine is only taken while searching")
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        // setoption name <words> [value <words>]
+        let value_at = args.iter().position(|&w| w == "value").unwrap_or(args.len());
+        if args.first() != Some(&"name") {
+            println!("info string expected setoption name <id> [value <x>]");
+            return;
+        }
+        let name = args[1..value_at].join(" ");
+        let value = args.get(value_at + 1..).map(|v| v.join(" ")).unwrap_or_default();
+        match name.to_lowercase().as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(mb) if (1..=MAX_HASH).contains(&mb) => self.engine = Some(Engine::with_hash_size(mb)),
+                _ => println!("info string Hash must be between 1 and {}", MAX_HASH),
+            },
+            "clear hash" => self.engine().clear(),
+            _ => println!("info string unknown option: {}", name),
+        }
+    }
+
+    fn go(&mut self, limits: SearchLimits) {
+        let mut engine = self.engine.take().expect("engine is only taken while searching");
+        let stop = engine.stop_flag();
+        let board = self.board;
+        let handle = thread::spawn(move || {
+            let result = engine.search(&board, limits, |r| println!("{}", info_line(&board, r)));
+            match result.best_move {
+                Some(m) => println!("bestmove {}", board.format_move(&m, Notation::Long)),
+                None => println!("bestmove (none)"),
+            }
+            engine
+        });
+        self.search = Some(RunningSearch { handle, stop, limits });
+    }
+
+    // stops any search in progress and waits for it to report its move
+    fn finish_search(&mut self) {
+        if let Some(search) = &self.search {
+            search.stop.store(true, Ordering::Relaxed);
+        }
+        self.wait_for_search();
+    }
+
+    // lets a search with limits run to them, since scripts often send their
+    // next commands without waiting for the move, but stops an infinite one
+    fn settle_search(&mut self) {
+        match &self.search {
+            Some(search) if search.limits == SearchLimits::default() => self.finish_search(),
+            _ => self.wait_for_search(),
+        }
+    }
+
+    fn wait_for_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            let engine = search.handle.join().expect("search thread panicked");
+            // the search may have finished on its own just before being told to stop
+            search.stop.store(false, Ordering::Relaxed);
+            self.engine = Some(engine);
+        }
+    }
+}
+
+// position (startpos [<preset>] | fen <fields>) [moves <move>...]
+fn parse_position(args: &[&str]) -> Result<Board, String> {
+    let moves_at = args.iter().position(|&w| w == "moves").unwrap_or(args.len());
+    let (setup, moves) = (&args[..moves_at], args.get(moves_at + 1..).unwrap_or(&[]));
+    let mut board = match setup {
+        ["startpos"] => Board::new(),
+        ["startpos", preset] => Board::preset(preset).ok_or_else(|| format!("unknown preset: {}", preset))?,
+        ["fen", fields @ ..] => Board::from_fen(&fields.join(" ")).map_err(|e| format!("invalid FEN: {}", e))?,
+        _ => return Err("expected position startpos [<preset>] or position fen <fen>".to_owned()),
+    };
+    for text in moves {
+        let m = board.parse_move(text).map_err(|e| format!("invalid move {}: {}", text, e))?;
+        board.make_move(&m);
+    }
+    Ok(board)
+}
+
+// go [depth <n>] [nodes <n>] [movetime <ms>] [wtime <ms>] [btime <ms>]
+//    [winc <ms>] [binc <ms>] [movestogo <n>] [infinite]
+fn parse_go(args: &[&str], board: &Board) -> Result<SearchLimits, String> {
+    let mut limits = SearchLimits::default();
+    let (mut clock, mut increment, mut moves_to_go) = (None, 0, None);
+    let white = board.side_to_move() == Colors::White;
+    let mut words = args.iter();
+    while let Some(&word) = words.next() {
+        if word == "infinite" {
+            continue;
+        }
+        let value = words.next()
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| format!("go {} needs a number", word))?;
+        match word {
+            "depth" => limits.depth = Some(value as u32),
+            "nodes" => limits.nodes = Some(value),
+            "movetime" => limits.time = Some(Duration::from_millis(value)),
+            "wtime" if white => clock = Some(value),
+            "btime" if !white => clock = Some(value),
+            "winc" if white => increment = value,
+            "binc" if !white => increment = value,
+            "movestogo" => moves_to_go = Some(value),
+            "wtime" | "btime" | "winc" | "binc" => {},
+            _ => return Err(format!("unknown go parameter: {}", word)),
+        }
+    }
+    if let (Some(remaining), None) = (clock, limits.time) {
+        // an even share of what's left, plus most of the increment
+        let share = remaining / moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1) + increment * 3 / 4;
+        let budget = share.min(remaining.saturating_sub(MOVE_OVERHEAD)).max(1);
+        limits.time = Some(Duration::from_millis(budget));
+    }
+    Ok(limits)
+}
+
+fn info_line(board: &Board, r: &SearchResult) -> String {
+    let score = match r.mate_in() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", r.score),
+    };
+    let millis = r.elapsed.as_millis() as u64;
+    let nps = r.nodes * 1000 / millis.max(1);
+    let mut line = format!("info depth {} score {} nodes {} time {} nps {} pv", r.depth, score, r.nodes, millis, nps);
+    let mut board = *board;
+    for m in &r.pv {
+        line.push(' ');
+        line.push_str(&board.format_move(m, Notation::Long));
+        board.make_move(m);
+    }
+    line
+}
+
+fn main() {
+    let mut session = Session::new();
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if !session.command(&line) {
+            return;
+        }
+    }
+    session.settle_search();
+}
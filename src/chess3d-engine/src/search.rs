@@ -0,0 +1,371 @@
+//! Negamax alpha-beta with principal variation search, a check extension and
+//! quiescence search over captures and promotions.
+
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::Instant;
+
+use chess3d::bitboard::{ color_index, kind_index, square_index, square_location, SQUARES };
+use chess3d::{ Board, BoardState, Move, Pieces };
+
+use crate::eval::{ Evaluator, PIECE_VALUES };
+use crate::tt::{ Bound, Entry, PackedMove, TranspositionTable };
+use crate::{ SearchLimits, MATE, MAX_PLY };
+
+const INFINITY: i32 = MATE + 1;
+// scores beyond this are mates, whose distance is counted from the root
+const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+// move ordering tiers; history scores stay below the killers
+const TT_MOVE: i32 = 1_000_000;
+const CAPTURE: i32 = 100_000;
+const KILLER: i32 = 90_000;
+const HISTORY_LIMIT: i32 = 80_000;
+
+// what a capture must be able to gain over alpha to be searched in quiescence
+const DELTA_MARGIN: i32 = 200;
+
+// how many nodes pass between checks of the clock and the stop flag
+const CHECK_INTERVAL: u64 = 1024;
+
+pub struct Search<'a> {
+    board: Board,
+    evaluator: Evaluator,
+    tt: &'a mut TranspositionTable,
+    stop: &'a AtomicBool,
+    limits: SearchLimits,
+    start: Instant,
+    // the iteration being searched; depth 1 always runs to completion
+    depth: u32,
+    stopped: bool,
+    pub nodes: u64,
+    pub quiescence_nodes: u64,
+    // the best line found from each ply, triangular: pv[ply] continues pv[ply + 1]
+    pv: Vec<Vec<Move>>,
+    // two quiet moves per ply that last caused a beta cutoff
+    killers: Vec<[Option<Move>; 2]>,
+    // cutoffs by quiet moves, indexed by colour, piece kind and destination
+    history: Vec<i32>,
+    // hashes of the positions from the root to the current node
+    path: Vec<u64>,
+}
+
+impl<'a> Search<'a> {
+    pub fn new(board: &Board, tt: &'a mut TranspositionTable, stop: &'a AtomicBool, limits: SearchLimits, start: Instant) -> Search<'a> {
+        Search {
+            board: *board,
+            evaluator: Evaluator::new(board.dimensions()),
+            tt,
+            stop,
+            limits,
+            start,
+            depth: 0,
+            stopped: false,
+            nodes: 0,
+            quiescence_nodes: 0,
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            killers: vec![[None; 2]; MAX_PLY + 1],
+            history: vec![0; 2 * 6 * SQUARES],
+            path: Vec::with_capacity(MAX_PLY),
+        }
+    }
+
+    /// Searches to `depth` and returns the score; the line is left in `pv`.
+    pub fn root(&mut self, depth: u32) -> i32 {
+        self.depth = depth;
+        self.negamax(depth, -INFINITY, INFINITY, 0)
+    }
+
+    pub fn pv(&self) -> Vec<Move> {
+        self.pv[0].clone()
+    }
+
+    /// True if the last iteration was cut short.
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// True if a limit has been reached or a stop requested.
+    pub fn limit_reached(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+            || self.limits.nodes.is_some_and(|n| self.nodes >= n)
+            || self.limits.time.is_some_and(|t| self.start.elapsed() >= t)
+    }
+
+    fn visit(&mut self) {
+        self.nodes += 1;
+        if self.depth > 1 && self.nodes.is_multiple_of(CHECK_INTERVAL) && self.limit_reached() {
+            self.stopped = true;
+        }
+    }
+
+    fn negamax(&mut self, depth: u32, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.pv[ply].clear();
+        let hash = self.board.hash();
+        if ply > 0 && (self.board.halfmove_clock() >= 100 || self.path.contains(&hash)) {
+            return 0;
+        }
+        let us = self.board.side_to_move();
+        let in_check = self.board.is_in_check(us);
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth == 0 || ply >= MAX_PLY - 1 {
+            return self.quiescence(alpha, beta, ply);
+        }
+        self.visit();
+        if self.stopped {
+            return 0;
+        }
+
+        let mut tt_move = None;
+        if let Some(entry) = self.tt.probe(hash) {
+            tt_move = entry.best;
+            // principal variation nodes search on so the line stays whole
+            if ply > 0 && entry.depth >= depth && beta - alpha == 1 {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {},
+                }
+            }
+        }
+
+        let original_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        let mut legal = 0;
+        self.path.push(hash);
+        for (m, _) in self.ordered_moves(tt_move, ply, false) {
+            let quiet = self.is_quiet(&m);
+            let undo = self.board.make_move(&m);
+            if self.board.is_in_check(us) {
+                self.board.unmake_move(&undo);
+                continue;
+            }
+            legal += 1;
+            let score = if legal == 1 {
+                -self.negamax(depth - 1, -beta, -alpha, ply + 1)
+            } else {
+                let score = -self.negamax(depth - 1, -alpha - 1, -alpha, ply + 1);
+                if score > alpha && score < beta {
+                    -self.negamax(depth - 1, -beta, -alpha, ply + 1)
+                } else {
+                    score
+                }
+            };
+            self.board.unmake_move(&undo);
+            if self.stopped {
+                self.path.pop();
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(m);
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(m, ply);
+                    if alpha >= beta {
+                        if quiet {
+                            self.record_cutoff(m, depth, ply);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        self.path.pop();
+
+        if legal == 0 {
+            return if in_check { -(MATE - ply as i32) } else { 0 };
+        }
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(Entry {
+            key: hash,
+            best: best_move.as_ref().map(PackedMove::new),
+            score: score_to_tt(best, ply),
+            depth,
+            bound,
+        });
+        best
+    }
+
+    // searches captures and promotions until the position is quiet, or every
+    // evasion while in check
+    fn quiescence(&mut self, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.pv[ply].clear();
+        self.visit();
+        self.quiescence_nodes += 1;
+        if self.stopped {
+            return 0;
+        }
+        let us = self.board.side_to_move();
+        let in_check = self.board.is_in_check(us);
+        if ply >= MAX_PLY - 1 {
+            return self.evaluator.evaluate(&self.board);
+        }
+
+        let mut best = -INFINITY;
+        let mut stand_pat = -INFINITY;
+        if !in_check {
+            stand_pat = self.evaluator.evaluate(&self.board);
+            best = stand_pat;
+            if best >= beta {
+                return best;
+            }
+            alpha = alpha.max(best);
+        }
+
+        let mut legal = 0;
+        for (m, _) in self.ordered_moves(None, ply, !in_check) {
+            // delta pruning: skip captures that can't raise alpha even with a margin
+            if !in_check && stand_pat + self.gain(&m) + DELTA_MARGIN <= alpha {
+                continue;
+            }
+            let undo = self.board.make_move(&m);
+            if self.board.is_in_check(us) {
+                self.board.unmake_move(&undo);
+                continue;
+            }
+            legal += 1;
+            let score = -self.quiescence(-beta, -alpha, ply + 1);
+            self.board.unmake_move(&undo);
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    self.update_pv(m, ply);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if in_check && legal == 0 {
+            return -(MATE - ply as i32);
+        }
+        best
+    }
+
+    // the side to move's pseudo-legal moves, best first
+    fn ordered_moves(&self, tt_move: Option<PackedMove>, ply: usize, noisy_only: bool) -> Vec<(Move, i32)> {
+        let us = self.board.side_to_move();
+        let mut moves = Vec::new();
+        for square in self.board.color_set(us).squares() {
+            for m in self.board.piece_moves(square_location(square)) {
+                if noisy_only && self.is_quiet(&m) {
+                    continue;
+                }
+                let score = self.order_score(&m, tt_move, ply);
+                moves.push((m, score));
+            }
+        }
+        moves.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        moves
+    }
+
+    fn order_score(&self, m: &Move, tt_move: Option<PackedMove>, ply: usize) -> i32 {
+        if tt_move.is_some_and(|t| t.matches(m)) {
+            return TT_MOVE;
+        }
+        let mut score = 0;
+        if let Some(victim) = self.victim(m) {
+            // most valuable victim first, then least valuable attacker
+            score += CAPTURE + PIECE_VALUES[kind_index(victim)] - kind_index(m.piece().1) as i32;
+        }
+        if let Some(p) = m.promotion() {
+            score += CAPTURE + PIECE_VALUES[kind_index(p)];
+        }
+        if score > 0 {
+            return score;
+        }
+        match self.killers[ply] {
+            [Some(k), _] if k == *m => KILLER,
+            [_, Some(k)] if k == *m => KILLER - 1,
+            _ => self.history[self.history_index(m)],
+        }
+    }
+
+    // the kind of piece `m` captures, if any
+    fn victim(&self, m: &Move) -> Option<Pieces> {
+        match self.board.at(m.to()) {
+            BoardState::Piece((_, p)) => Some(p),
+            BoardState::Empty => match (m.piece().1, self.board.en_passant()) {
+                (Pieces::Pawn(_), Some(ep)) if ep.target == m.to() => Some(Pieces::Pawn(true)),
+                _ => None,
+            },
+        }
+    }
+
+    // the material `m` wins at most, before any recapture
+    fn gain(&self, m: &Move) -> i32 {
+        let captured = self.victim(m).map_or(0, |p| PIECE_VALUES[kind_index(p)]);
+        let promoted = m.promotion().map_or(0, |p| PIECE_VALUES[kind_index(p)] - PIECE_VALUES[0]);
+        captured + promoted
+    }
+
+    fn is_quiet(&self, m: &Move) -> bool {
+        m.promotion().is_none() && self.victim(m).is_none()
+    }
+
+    fn history_index(&self, m: &Move) -> usize {
+        let (c, p) = m.piece();
+        (color_index(c) * 6 + kind_index(p)) * SQUARES + square_index(m.to())
+    }
+
+    fn record_cutoff(&mut self, m: Move, depth: u32, ply: usize) {
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(m) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
+        let index = self.history_index(&m);
+        self.history[index] += (depth * depth) as i32;
+        if self.history[index] > HISTORY_LIMIT {
+            for h in self.history.iter_mut() {
+                *h /= 2;
+            }
+        }
+    }
+
+    fn update_pv(&mut self, m: Move, ply: usize) {
+        let (line, rest) = self.pv.split_at_mut(ply + 1);
+        let line = &mut line[ply];
+        line.clear();
+        line.push(m);
+        line.extend_from_slice(&rest[0]);
+    }
+}
+
+// mate scores are stored relative to the node rather than the root, so they
+// stay correct when the position is reached at another ply
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score + ply as i32
+    } else if score < -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score > MATE_BOUND {
+        score - ply as i32
+    } else if score < -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
@@ -0,0 +1,82 @@
+//! The transposition table: search results keyed by the board's Zobrist hash,
+//! in a fixed number of slots that newer results overwrite.
+
+use chess3d::bitboard::square_index;
+use chess3d::{ Move, Pieces };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    // the score is exact
+    Exact,
+    // the search failed high; the true score is at least this
+    Lower,
+    // no move reached alpha; the true score is at most this
+    Upper,
+}
+
+// a move packed into 32 bits: 9 bits each for the squares and 3 for the promotion
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackedMove(u32);
+
+impl PackedMove {
+    pub fn new(m: &Move) -> PackedMove {
+        let promotion = match m.promotion() {
+            None => 0,
+            Some(Pieces::Queen) => 1,
+            Some(Pieces::Rook(_)) => 2,
+            Some(Pieces::Bishop) => 3,
+            Some(_) => 4,
+        };
+        PackedMove(square_index(m.from()) as u32 | (square_index(m.to()) as u32) << 9 | promotion << 18)
+    }
+
+    pub fn matches(&self, m: &Move) -> bool {
+        *self == PackedMove::new(m)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub best: Option<PackedMove>,
+    pub score: i32,
+    pub depth: u32,
+    pub bound: Bound,
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+}
+
+impl TranspositionTable {
+    /// A table of roughly `megabytes` megabytes.
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        let slots = (megabytes.max(1) << 20) / std::mem::size_of::<Option<Entry>>();
+        // a power of two so the slot is the low bits of the key
+        let slots = if slots.is_power_of_two() { slots } else { slots.next_power_of_two() / 2 };
+        TranspositionTable { entries: vec![None; slots] }
+    }
+
+    fn slot(&self, key: u64) -> usize {
+        (key as usize) & (self.entries.len() - 1)
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        self.entries[self.slot(key)].filter(|e| e.key == key)
+    }
+
+    // prefers deeper results for the same position, and always replaces others
+    pub fn store(&mut self, entry: Entry) {
+        let slot = self.slot(entry.key);
+        match self.entries[slot] {
+            Some(old) if old.key == entry.key && old.depth > entry.depth && entry.bound != Bound::Exact => {},
+            _ => self.entries[slot] = Some(entry),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for e in self.entries.iter_mut() {
+            *e = None;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Searches of small positions with known answers, and the limits that end a
+//! search early. Most positions are single-layer boards, which play ordinary
+//! chess and keep the searches quick.
+
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use chess3d::{ Board, Notation };
+use chess3d_engine::{ best_move, Engine, SearchLimits, MATE };
+
+fn board(fen: &str) -> Board {
+    Board::from_fen(fen).unwrap()
+}
+
+#[test]
+fn mate_in_one() {
+    let b = board("6k1/5ppp/8/8/8/8/8/R5K1 w - 0 1");
+    let result = best_move(&b, SearchLimits::depth(3));
+    let m = result.best_move.unwrap();
+    assert_eq!(b.format_move(&m, Notation::Long), "Ra1A-a8A#");
+    assert_eq!(result.score, MATE - 1);
+    assert_eq!(result.mate_in(), Some(1));
+    assert_eq!(result.pv, [m]);
+}
+
+#[test]
+fn mate_in_two_survives_the_transposition_table() {
+    // Rb7 and then Ra8#; the second search starts from what the first stored
+    let b = board("7k/8/8/8/8/8/8/RR4K1 w - 0 1");
+    let mut engine = Engine::new();
+    for _ in 0..2 {
+        let result = engine.search(&b, SearchLimits::depth(4), |_| {});
+        assert_eq!(result.mate_in(), Some(2), "{:?}", result);
+        assert_eq!(result.score, MATE - 3);
+        assert_eq!(result.pv.len(), 3);
+    }
+}
+
+#[test]
+fn being_mated() {
+    let b = board("R5k1/5ppp/8/8/8/8/8/6K1 b - 0 1");
+    let result = best_move(&b, SearchLimits::depth(3));
+    assert_eq!(result.best_move, None);
+    assert!(result.pv.is_empty());
+    assert_eq!(result.score, -MATE);
+}
+
+#[test]
+fn stalemate() {
+    let b = board("k7/2Q5/1K6/8/8/8/8/8 b - 0 1");
+    let result = best_move(&b, SearchLimits::depth(3));
+    assert_eq!(result.best_move, None);
+    assert_eq!(result.score, 0);
+    assert_eq!(result.mate_in(), None);
+}
+
+#[test]
+fn node_limit() {
+    let limits = SearchLimits { nodes: Some(5_000), ..SearchLimits::default() };
+    let result = best_move(&Board::new(), limits);
+    assert!(result.best_move.is_some());
+    assert!(result.depth >= 1);
+    // the limit is checked every 1024 nodes
+    assert!(result.nodes < 5_000 + 1024, "{} nodes", result.nodes);
+}
+
+#[test]
+fn depth_limit() {
+    let mut depths = Vec::new();
+    let result = Engine::new().search(&Board::new(), SearchLimits::depth(2), |r| depths.push(r.depth));
+    assert_eq!(depths, [1, 2]);
+    assert_eq!(result.depth, 2);
+}
+
+#[test]
+fn stop_flag() {
+    let mut engine = Engine::new();
+    let stop = engine.stop_flag();
+
+    // set before the search starts, it still lets depth 1 finish
+    stop.store(true, Ordering::Relaxed);
+    let result = engine.search(&Board::new(), SearchLimits::default(), |_| {});
+    assert_eq!(result.depth, 1);
+    assert!(result.best_move.is_some());
+    assert!(!stop.load(Ordering::Relaxed));
+
+    // and set during a search with no limits, it ends it
+    let stopper = {
+        let stop = stop.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            stop.store(true, Ordering::Relaxed);
+        })
+    };
+    let start = Instant::now();
+    let result = engine.search(&Board::new(), SearchLimits::default(), |_| {});
+    stopper.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert!(result.best_move.is_some());
+    assert!(!stop.load(Ordering::Relaxed));
+}
@@ -0,0 +1,296 @@
+//! 512-bit sets of squares and the precomputed attack tables used by move
+//! generation. Square `(x, y, z)` is bit `y * 8 + z` of word `x`, so walking
+//! along any direction visits strictly increasing or strictly decreasing indices.
+
+use std::ops;
+use std::sync::OnceLock;
+
+use serde::{ Serialize, Deserialize };
+
+use crate::{ Colors, Location, Pieces, KNIGHT_OFFSETS, PAWN_CAPTURE_OFFSETS, QUEEN_DIRECTIONS, TRIAGONAL_DIRECTIONS };
+
+pub const SQUARES: usize = 512;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Bitboard([u64; 8]);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard([0; 8]);
+
+    pub fn from_square(square: usize) -> Bitboard {
+        let mut b = Bitboard::EMPTY;
+        b.insert(square);
+        b
+    }
+
+    pub fn contains(&self, square: usize) -> bool {
+        self.0[square / 64] & (1 << (square % 64)) != 0
+    }
+
+    pub fn insert(&mut self, square: usize) {
+        self.0[square / 64] |= 1 << (square % 64);
+    }
+
+    pub fn remove(&mut self, square: usize) {
+        self.0[square / 64] &= !(1 << (square % 64));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.iter().all(|&w| w == 0)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.0.iter().map(|w| w.count_ones()).sum()
+    }
+
+    /// The lowest square in the set.
+    pub fn first(&self) -> Option<usize> {
+        self.0.iter()
+            .enumerate()
+            .find(|(_, &w)| w != 0)
+            .map(|(i, w)| i * 64 + w.trailing_zeros() as usize)
+    }
+
+    /// The highest square in the set.
+    pub fn last(&self) -> Option<usize> {
+        self.0.iter()
+            .enumerate()
+            .rev()
+            .find(|(_, &w)| w != 0)
+            .map(|(i, w)| i * 64 + 63 - w.leading_zeros() as usize)
+    }
+
+    pub fn squares(&self) -> Squares {
+        Squares { bits: *self, word: 0 }
+    }
+}
+
+/// Iterates the squares of a `Bitboard` from lowest to highest.
+pub struct Squares {
+    bits: Bitboard,
+    word: usize,
+}
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.word < 8 {
+            let w = &mut self.bits.0[self.word];
+            if *w != 0 {
+                let bit = w.trailing_zeros() as usize;
+                *w &= *w - 1;
+                return Some(self.word * 64 + bit);
+            }
+            self.word += 1;
+        }
+        None
+    }
+}
+
+impl ops::BitAnd for Bitboard {
+    type Output = Bitboard;
+
+    fn bitand(mut self, other: Bitboard) -> Bitboard {
+        self &= other;
+        self
+    }
+}
+
+impl ops::BitAndAssign for Bitboard {
+    fn bitand_assign(&mut self, other: Bitboard) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a &= b;
+        }
+    }
+}
+
+impl ops::BitOr for Bitboard {
+    type Output = Bitboard;
+
+    fn bitor(mut self, other: Bitboard) -> Bitboard {
+        self |= other;
+        self
+    }
+}
+
+impl ops::BitOrAssign for Bitboard {
+    fn bitor_assign(&mut self, other: Bitboard) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a |= b;
+        }
+    }
+}
+
+impl ops::BitXor for Bitboard {
+    type Output = Bitboard;
+
+    fn bitxor(mut self, other: Bitboard) -> Bitboard {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a ^= b;
+        }
+        self
+    }
+}
+
+impl ops::Not for Bitboard {
+    type Output = Bitboard;
+
+    fn not(mut self) -> Bitboard {
+        for a in self.0.iter_mut() {
+            *a = !*a;
+        }
+        self
+    }
+}
+
+pub fn square_index(l: Location) -> usize {
+    (l.x * 64 + l.y * 8 + l.z) as usize
+}
+
+pub fn square_location(square: usize) -> Location {
+    let s = square as isize;
+    Location::new(s / 64, (s / 8) % 8, s % 8)
+}
+
+fn on_cube(l: Location) -> bool {
+    l.x >= 0 && l.x < 8 && l.y >= 0 && l.y < 8 && l.z >= 0 && l.z < 8
+}
+
+/// Index into the ray tables for one of the 26 directions to a neighbouring square.
+pub fn direction_index((dx, dy, dz): (isize, isize, isize)) -> usize {
+    ((dx + 1) * 9 + (dy + 1) * 3 + (dz + 1)) as usize
+}
+
+// the first non-zero component decides whether indices increase along a direction
+fn increasing((dx, dy, dz): (isize, isize, isize)) -> bool {
+    dx > 0 || (dx == 0 && (dy > 0 || (dy == 0 && dz > 0)))
+}
+
+/// Piece kinds that get their own bitboard, with first-move flags ignored.
+pub fn kind_index(p: Pieces) -> usize {
+    match p {
+        Pieces::Pawn(_) => 0,
+        Pieces::Knight => 1,
+        Pieces::Bishop => 2,
+        Pieces::Rook(_) => 3,
+        Pieces::Queen => 4,
+        Pieces::King(_) => 5,
+    }
+}
+
+pub fn color_index(c: Colors) -> usize {
+    match c {
+        Colors::White => 0,
+        Colors::Black => 1,
+    }
+}
+
+pub struct AttackTables {
+    knight: Vec<Bitboard>,
+    king: Vec<Bitboard>,
+    // the extra king steps allowed by triagonal rules
+    king_triagonal: Vec<Bitboard>,
+    // squares a pawn of each colour attacks from each square
+    pawn: [Vec<Bitboard>; 2],
+    // every square along a direction from each square, not including the square itself
+    rays: Vec<Vec<Bitboard>>,
+    // the union of the rays in all 26 directions
+    lines: Vec<Bitboard>,
+}
+
+impl AttackTables {
+    fn build() -> AttackTables {
+        let mut tables = AttackTables {
+            knight: vec![Bitboard::EMPTY; SQUARES],
+            king: vec![Bitboard::EMPTY; SQUARES],
+            king_triagonal: vec![Bitboard::EMPTY; SQUARES],
+            pawn: [vec![Bitboard::EMPTY; SQUARES], vec![Bitboard::EMPTY; SQUARES]],
+            rays: vec![vec![Bitboard::EMPTY; SQUARES]; 27],
+            lines: vec![Bitboard::EMPTY; SQUARES],
+        };
+
+        let offset_squares = |from: Location, offsets: &[(isize, isize, isize)], sign: isize| {
+            let mut b = Bitboard::EMPTY;
+            for &o in offsets {
+                let to = from + Location::from(o) * sign;
+                if on_cube(to) {
+                    b.insert(square_index(to));
+                }
+            }
+            b
+        };
+
+        for square in 0..SQUARES {
+            let from = square_location(square);
+            tables.knight[square] = offset_squares(from, &KNIGHT_OFFSETS, 1) | offset_squares(from, &KNIGHT_OFFSETS, -1);
+            tables.king[square] = offset_squares(from, &QUEEN_DIRECTIONS, 1) | offset_squares(from, &QUEEN_DIRECTIONS, -1);
+            tables.king_triagonal[square] = offset_squares(from, &TRIAGONAL_DIRECTIONS, 1) | offset_squares(from, &TRIAGONAL_DIRECTIONS, -1);
+            tables.pawn[color_index(Colors::White)][square] = offset_squares(from, &PAWN_CAPTURE_OFFSETS, 1);
+            tables.pawn[color_index(Colors::Black)][square] = offset_squares(from, &PAWN_CAPTURE_OFFSETS, -1);
+
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let d = Location::new(dx, dy, dz);
+                        if d == Location::new(0, 0, 0) {
+                            continue;
+                        }
+                        let mut ray = Bitboard::EMPTY;
+                        let mut l = from + d;
+                        while on_cube(l) {
+                            ray.insert(square_index(l));
+                            l = l + d;
+                        }
+                        tables.rays[direction_index((dx, dy, dz))][square] = ray;
+                        tables.lines[square] |= ray;
+                    }
+                }
+            }
+        }
+        tables
+    }
+
+    pub fn knight(&self, square: usize) -> Bitboard {
+        self.knight[square]
+    }
+
+    pub fn king(&self, square: usize) -> Bitboard {
+        self.king[square]
+    }
+
+    pub fn king_triagonal(&self, square: usize) -> Bitboard {
+        self.king_triagonal[square]
+    }
+
+    pub fn pawn(&self, c: Colors, square: usize) -> Bitboard {
+        self.pawn[color_index(c)][square]
+    }
+
+    /// Every square sharing a straight, diagonal or triagonal line with `square`.
+    pub fn lines(&self, square: usize) -> Bitboard {
+        self.lines[square]
+    }
+
+    pub fn ray(&self, square: usize, direction: (isize, isize, isize)) -> Bitboard {
+        self.rays[direction_index(direction)][square]
+    }
+
+    /// Squares a slider on `square` reaches along `direction`, up to and
+    /// including the first occupied square.
+    pub fn slide(&self, square: usize, direction: (isize, isize, isize), occupied: Bitboard) -> Bitboard {
+        let rays = &self.rays[direction_index(direction)];
+        let ray = rays[square];
+        let blockers = ray & occupied;
+        let blocker = if increasing(direction) { blockers.first() } else { blockers.last() };
+        match blocker {
+            Some(b) => ray ^ rays[b],
+            None => ray,
+        }
+    }
+}
+
+pub fn tables() -> &'static AttackTables {
+    static TABLES: OnceLock<AttackTables> = OnceLock::new();
+    TABLES.get_or_init(AttackTables::build)
+}
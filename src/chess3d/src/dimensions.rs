@@ -0,0 +1,135 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use serde::{ Serialize, Deserialize };
+
+use crate::bitboard::{ Bitboard, square_index };
+use crate::Location;
+
+/// The number of files, ranks and layers a board uses. Every board is stored
+/// in an 8x8x8 cube; the squares outside the dimensions are always empty and
+/// no piece can move onto them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawDimensions")]
+pub struct Dimensions {
+    files: isize,
+    ranks: isize,
+    layers: isize,
+}
+
+#[derive(Deserialize)]
+struct RawDimensions {
+    files: isize,
+    ranks: isize,
+    layers: isize,
+}
+
+impl TryFrom<RawDimensions> for Dimensions {
+    type Error = String;
+
+    fn try_from(raw: RawDimensions) -> Result<Dimensions, String> {
+        Dimensions::new(raw.files, raw.ranks, raw.layers)
+            .ok_or_else(|| format!("invalid board dimensions {}x{}x{}", raw.files, raw.ranks, raw.layers))
+    }
+}
+
+impl Dimensions {
+    /// The longest any axis can be.
+    pub const MAX: isize = 8;
+
+    pub const STANDARD: Dimensions = Dimensions { files: 8, ranks: 8, layers: 8 };
+    pub const RAUMSCHACH: Dimensions = Dimensions { files: 5, ranks: 5, layers: 5 };
+    pub const TRAINING: Dimensions = Dimensions { files: 4, ranks: 4, layers: 4 };
+    /// A single layer, which plays exactly like ordinary chess.
+    pub const FLAT: Dimensions = Dimensions { files: 8, ranks: 8, layers: 1 };
+
+    pub const PRESETS: [(&'static str, Dimensions); 4] = [
+        ("standard", Dimensions::STANDARD),
+        ("raumschach", Dimensions::RAUMSCHACH),
+        ("training", Dimensions::TRAINING),
+        ("flat", Dimensions::FLAT),
+    ];
+
+    /// Each axis must be between 1 and `MAX` long, with at least two ranks
+    /// so the two sides don't start on the same squares.
+    pub fn new(files: isize, ranks: isize, layers: isize) -> Option<Dimensions> {
+        let axis = 1..=Dimensions::MAX;
+        if axis.contains(&files) && axis.contains(&ranks) && axis.contains(&layers) && ranks >= 2 {
+            Some(Dimensions { files, ranks, layers })
+        } else {
+            None
+        }
+    }
+
+    pub fn preset(name: &str) -> Option<Dimensions> {
+        Dimensions::PRESETS.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, d)| d)
+    }
+
+    /// The name of the preset these dimensions match, if any.
+    pub fn name(&self) -> Option<&'static str> {
+        Dimensions::PRESETS.iter()
+            .find(|(_, d)| d == self)
+            .map(|&(n, _)| n)
+    }
+
+    pub fn files(&self) -> isize {
+        self.files
+    }
+
+    pub fn ranks(&self) -> isize {
+        self.ranks
+    }
+
+    pub fn layers(&self) -> isize {
+        self.layers
+    }
+
+    pub fn contains(&self, l: Location) -> bool {
+        (0..self.files).contains(&l.x) && (0..self.ranks).contains(&l.y) && (0..self.layers).contains(&l.z)
+    }
+
+    /// Every square on the board.
+    pub fn area(&self) -> Bitboard {
+        let mut area = Bitboard::EMPTY;
+        for x in 0..self.files {
+            for y in 0..self.ranks {
+                for z in 0..self.layers {
+                    area.insert(square_index(Location::new(x, y, z)));
+                }
+            }
+        }
+        area
+    }
+
+    // White's pieces, one rank per layer starting from layer 0, with a rank of
+    // pawns in front of each when there is room. Black's are mirrored through
+    // the centre of the board.
+    pub(crate) fn starting_ranks(&self) -> &'static [&'static str] {
+        match (self.files, self.layers) {
+            // Raumschach puts its pieces on the first two layers
+            (5, layers) if layers >= 4 => &["RNKNR", "BBQBB"],
+            (1, _) => &["K"],
+            (2, _) => &["QK"],
+            (3, _) => &["RKQ"],
+            (4, _) => &["RQKN"],
+            (5, _) => &["RNKQR"],
+            (6, _) => &["RNQKNR"],
+            (7, _) => &["RNBKQNR"],
+            _ => &["RNBQKBNR"],
+        }
+    }
+}
+
+impl Default for Dimensions {
+    fn default() -> Dimensions {
+        Dimensions::STANDARD
+    }
+}
+
+impl fmt::Display for Dimensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}x{}", self.files, self.ranks, self.layers)
+    }
+}
@@ -0,0 +1,334 @@
+//! A FEN-like text notation for positions. A position is written as six
+//! space-separated fields:
+//!
+//! ```text
+//! 8/8/8/8/8/8/PPPPPPPP/RNBQKBNR|8/8/8/8/8/8/8/8|...|rnbqkbnr/pppppppp/8/8/8/8/8/8 w - 0 1 -
+//! ```
+//!
+//! 1. The squares, one layer at a time from layer A upwards, separated by `|`.
+//!    Each layer lists its ranks from the highest down, separated by `/`, and
+//!    each rank lists its files from `a`. White pieces are upper case, Black
+//!    lower case and a digit skips that many empty squares. A `*` after a
+//!    pawn, rook or king means it has moved. The number of files, ranks and
+//!    layers gives the board's dimensions.
+//! 2. The side to move, `w` or `b`.
+//! 3. The en passant square and the pawn that can be taken there, as
+//!    `target:pawn` like `d3A:d4A`, or `-`.
+//! 4. The halfmove clock.
+//! 5. The fullmove number.
+//! 6. The pieces allowed triagonal moves, any of `B`, `Q` and `K`, or `-`.
+//!    This field may be left out when there are none.
+
+use std::fmt;
+
+use crate::{ Board, BoardState, Colors, Dimensions, EnPassant, Location, Pieces, RawBoard, RuleSet };
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    // the name of the first field that is missing
+    MissingField(&'static str),
+    TooManyFields,
+    // layer and rank are the z and y coordinates of the offending rank
+    InvalidCharacter { layer: usize, rank: usize, character: char },
+    // a `*` after a piece that has no moved flag, or with no piece before it
+    MisplacedMovedFlag { layer: usize, rank: usize },
+    // more files than any board has
+    TooManyFiles { layer: usize, rank: usize, files: isize },
+    // a rank whose length differs from the first rank's, which is `expected`
+    RankLength { layer: usize, rank: usize, files: isize, expected: isize },
+    RankCount { layer: usize, ranks: isize, expected: isize },
+    InvalidDimensions { files: isize, ranks: isize, layers: isize },
+    InvalidSideToMove(String),
+    InvalidEnPassant(String),
+    InvalidNumber { field: &'static str, value: String },
+    InvalidRules(String),
+}
+
+// layers are named by letter, as in square names
+fn layer_name(layer: usize) -> String {
+    match layer {
+        0..=25 => ((b'A' + layer as u8) as char).to_string(),
+        _ => (layer + 1).to_string(),
+    }
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing the {} field", field),
+            FenError::TooManyFields => write!(f, "too many fields"),
+            FenError::InvalidCharacter { layer, rank, character } =>
+                write!(f, "unexpected '{}' in rank {} of layer {}", character, rank + 1, layer_name(*layer)),
+            FenError::MisplacedMovedFlag { layer, rank } =>
+                write!(f, "'*' does not follow a pawn, rook or king in rank {} of layer {}", rank + 1, layer_name(*layer)),
+            FenError::TooManyFiles { layer, rank, files } =>
+                write!(f, "rank {} of layer {} has {} files, more than the {} allowed", rank + 1, layer_name(*layer), files, Dimensions::MAX),
+            FenError::RankLength { layer, rank, files, expected } =>
+                write!(f, "rank {} of layer {} has {} files, but the first rank has {}", rank + 1, layer_name(*layer), files, expected),
+            FenError::RankCount { layer, ranks, expected } =>
+                write!(f, "layer {} has {} ranks, expected {}", layer_name(*layer), ranks, expected),
+            FenError::InvalidDimensions { files, ranks, layers } =>
+                write!(f, "a {}x{}x{} board is not supported", files, ranks, layers),
+            FenError::InvalidSideToMove(s) => write!(f, "side to move must be 'w' or 'b', not '{}'", s),
+            FenError::InvalidEnPassant(s) => write!(f, "invalid en passant field '{}'", s),
+            FenError::InvalidNumber { field, value } => write!(f, "invalid {} '{}'", field, value),
+            FenError::InvalidRules(s) => write!(f, "invalid triagonal pieces '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+// the squares of one rank, from file a, with None for an empty square
+type Rank = Vec<Option<(Colors, Pieces)>>;
+
+// the ranks of one layer, listed as they appear in the string
+fn parse_layer(layer: usize, text: &str) -> Result<Vec<Rank>, FenError> {
+    let texts: Vec<&str> = text.split('/').collect();
+    let mut ranks = Vec::new();
+    for (i, text) in texts.iter().enumerate() {
+        let rank = texts.len() - 1 - i;
+        let mut squares: Rank = Vec::new();
+        for character in text.chars() {
+            if let Some(n) = character.to_digit(10).filter(|&n| n > 0) {
+                squares.extend((0..n).map(|_| None));
+                continue;
+            }
+            if character == '*' {
+                let piece = match squares.last_mut() {
+                    Some(Some((_, p))) => p,
+                    _ => return Err(FenError::MisplacedMovedFlag { layer, rank }),
+                };
+                *piece = match *piece {
+                    Pieces::Pawn(false) => Pieces::Pawn(true),
+                    Pieces::Rook(false) => Pieces::Rook(true),
+                    Pieces::King(false) => Pieces::King(true),
+                    _ => return Err(FenError::MisplacedMovedFlag { layer, rank }),
+                };
+                continue;
+            }
+            let color = if character.is_ascii_uppercase() { Colors::White } else { Colors::Black };
+            let piece = match character.to_ascii_uppercase() {
+                'P' => Pieces::Pawn(false),
+                'R' => Pieces::Rook(false),
+                'N' => Pieces::Knight,
+                'B' => Pieces::Bishop,
+                'Q' => Pieces::Queen,
+                'K' => Pieces::King(false),
+                _ => return Err(FenError::InvalidCharacter { layer, rank, character }),
+            };
+            squares.push(Some((color, piece)));
+        }
+        ranks.push(squares);
+    }
+    Ok(ranks)
+}
+
+fn parse_number(field: &'static str, value: &str) -> Result<u32, FenError> {
+    value.parse().map_err(|_| FenError::InvalidNumber { field, value: value.to_owned() })
+}
+
+// the pieces with triagonal moves, like `BQK`, or `-` for none
+pub(crate) fn parse_rules(text: &str) -> Result<RuleSet, FenError> {
+    let mut rules = RuleSet::CLASSIC;
+    if text == "-" {
+        return Ok(rules);
+    }
+    for c in text.chars() {
+        let flag = match c {
+            'B' => &mut rules.triagonal_bishops,
+            'Q' => &mut rules.triagonal_queens,
+            'K' => &mut rules.triagonal_kings,
+            _ => return Err(FenError::InvalidRules(text.to_owned())),
+        };
+        if *flag {
+            return Err(FenError::InvalidRules(text.to_owned()));
+        }
+        *flag = true;
+    }
+    Ok(rules)
+}
+
+pub(crate) fn format_rules(rules: RuleSet) -> String {
+    let mut triagonal = String::new();
+    for &(flag, c) in [
+        (rules.triagonal_bishops, 'B'),
+        (rules.triagonal_queens, 'Q'),
+        (rules.triagonal_kings, 'K'),
+    ].iter() {
+        if flag {
+            triagonal.push(c);
+        }
+    }
+    if triagonal.is_empty() {
+        triagonal.push('-');
+    }
+    triagonal
+}
+
+// the en passant field names a pawn of the side that just moved, standing
+// one step beyond an empty target square in one of its forward directions
+fn parse_en_passant(text: &str, board: &Board) -> Result<Option<EnPassant>, FenError> {
+    if text == "-" {
+        return Ok(None);
+    }
+    let error = || FenError::InvalidEnPassant(text.to_owned());
+    let mut squares = text.split(':').map(Location::from_square_name);
+    let (target, pawn) = match (squares.next(), squares.next(), squares.next()) {
+        (Some(Some(target)), Some(Some(pawn)), None) => (target, pawn),
+        _ => return Err(error()),
+    };
+    let dims = board.dimensions();
+    if !dims.contains(target) || !dims.contains(pawn) || board.at(target) != BoardState::Empty {
+        return Err(error());
+    }
+    let mover = board.side_to_move.opponent();
+    if !matches!(board.at(pawn), BoardState::Piece((c, Pieces::Pawn(_))) if c == mover) {
+        return Err(error());
+    }
+    let sign = if mover == Colors::White { 1 } else { -1 };
+    let stepped = crate::PAWN_OFFSETS.iter().any(|&o| target + Location::from(o) * sign == pawn);
+    if !stepped {
+        return Err(error());
+    }
+    Ok(Some(EnPassant { target, pawn }))
+}
+
+impl Board {
+    /// Reads a position written in the notation described in the `fen` module.
+    pub fn from_fen(fen: &str) -> Result<Board, FenError> {
+        let mut fields = fen.split_whitespace();
+        let mut field = |name| fields.next().ok_or(FenError::MissingField(name));
+        let placement = field("placement")?;
+        let side = field("side to move")?;
+        let en_passant = field("en passant")?;
+        let halfmove = field("halfmove clock")?;
+        let fullmove = field("fullmove number")?;
+        let rules = fields.next().map(parse_rules).transpose()?.unwrap_or_default();
+        if fields.next().is_some() {
+            return Err(FenError::TooManyFields);
+        }
+
+        let layers = placement.split('|')
+            .enumerate()
+            .map(|(layer, text)| parse_layer(layer, text))
+            .collect::<Result<Vec<_>, _>>()?;
+        // an overlong first rank would otherwise be taken as the length the
+        // others are wrong against
+        for (layer, ranks) in layers.iter().enumerate() {
+            for (i, squares) in ranks.iter().enumerate() {
+                if squares.len() as isize > Dimensions::MAX {
+                    let rank = ranks.len() - 1 - i;
+                    return Err(FenError::TooManyFiles { layer, rank, files: squares.len() as isize });
+                }
+            }
+        }
+        let expected_ranks = layers[0].len() as isize;
+        let expected_files = layers[0][0].len() as isize;
+        for (layer, ranks) in layers.iter().enumerate() {
+            if ranks.len() as isize != expected_ranks {
+                return Err(FenError::RankCount { layer, ranks: ranks.len() as isize, expected: expected_ranks });
+            }
+            for (i, squares) in ranks.iter().enumerate() {
+                let rank = ranks.len() - 1 - i;
+                if squares.len() as isize != expected_files {
+                    return Err(FenError::RankLength { layer, rank, files: squares.len() as isize, expected: expected_files });
+                }
+            }
+        }
+        let (files, ranks, layer_count) = (expected_files, expected_ranks, layers.len() as isize);
+        let dimensions = Dimensions::new(files, ranks, layer_count)
+            .ok_or(FenError::InvalidDimensions { files, ranks, layers: layer_count })?;
+
+        let mut board = [[[BoardState::Empty; 8]; 8]; 8];
+        for (z, layer) in layers.iter().enumerate() {
+            for (i, rank) in layer.iter().enumerate() {
+                let y = ranks as usize - 1 - i;
+                for (x, &square) in rank.iter().enumerate() {
+                    if let Some(piece) = square {
+                        board[x][y][z] = BoardState::Piece(piece);
+                    }
+                }
+            }
+        }
+
+        let side_to_move = match side {
+            "w" => Colors::White,
+            "b" => Colors::Black,
+            _ => return Err(FenError::InvalidSideToMove(side.to_owned())),
+        };
+        let fullmove_number = parse_number("fullmove number", fullmove)?;
+        if fullmove_number == 0 {
+            return Err(FenError::InvalidNumber { field: "fullmove number", value: fullmove.to_owned() });
+        }
+        let mut board = Board::from_raw(RawBoard {
+            board,
+            is_running: true,
+            side_to_move,
+            halfmove_clock: parse_number("halfmove clock", halfmove)?,
+            fullmove_number,
+            en_passant: None,
+            rules,
+            dimensions,
+        });
+        board.en_passant = parse_en_passant(en_passant, &board)?;
+        board.rebuild();
+        Ok(board)
+    }
+
+    /// Writes the position in the notation described in the `fen` module.
+    pub fn to_fen(&self) -> String {
+        let d = self.dimensions;
+        let mut layers = Vec::new();
+        for z in 0..d.layers() {
+            let mut ranks = Vec::new();
+            for y in (0..d.ranks()).rev() {
+                let mut rank = String::new();
+                let mut empty = 0;
+                for x in 0..d.files() {
+                    match self.at((x, y, z)) {
+                        BoardState::Empty => empty += 1,
+                        BoardState::Piece((c, p)) => {
+                            if empty > 0 {
+                                rank.push_str(&empty.to_string());
+                                empty = 0;
+                            }
+                            rank.push_str(&BoardState::Piece((c, p)).to_string());
+                            if let Pieces::Pawn(true) | Pieces::Rook(true) | Pieces::King(true) = p {
+                                rank.push('*');
+                            }
+                        }
+                    }
+                }
+                if empty > 0 {
+                    rank.push_str(&empty.to_string());
+                }
+                ranks.push(rank);
+            }
+            layers.push(ranks.join("/"));
+        }
+
+        let side = match self.side_to_move {
+            Colors::White => "w",
+            Colors::Black => "b",
+        };
+        let en_passant = match self.en_passant {
+            Some(ep) => format!(
+                "{}:{}",
+                ep.target.square_name().unwrap_or_default(),
+                ep.pawn.square_name().unwrap_or_default(),
+            ),
+            None => "-".to_owned(),
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            layers.join("|"),
+            side,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number,
+            format_rules(self.rules),
+        )
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use serde::{ Serialize, Deserialize };
+
+use crate::{ Board, Colors, Move, MoveError, MoveOutcome, Outcome, Undo };
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum GameResult {
+    Checkmate { winner: Colors },
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+}
+
+impl GameResult {
+    pub fn winner(&self) -> Option<Colors> {
+        match self {
+            GameResult::Checkmate { winner } => Some(*winner),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GameResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameResult::Checkmate { winner } => write!(f, "Checkmate, {:?} wins", winner),
+            GameResult::Stalemate => write!(f, "Draw by stalemate"),
+            GameResult::ThreefoldRepetition => write!(f, "Draw by threefold repetition"),
+            GameResult::FiftyMoveRule => write!(f, "Draw by the fifty-move rule"),
+            GameResult::InsufficientMaterial => write!(f, "Draw by insufficient material"),
+        }
+    }
+}
+
+/// A `Board` together with the moves played on it. Ends the game on
+/// checkmate, stalemate, threefold repetition, the fifty-move rule or
+/// insufficient material.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Game {
+    board: Board,
+    moves: Vec<Move>,
+    history: Vec<Undo>,
+    // hash of every position reached, starting with the initial one
+    positions: Vec<u64>,
+    result: Option<GameResult>,
+}
+
+impl Game {
+    pub fn new() -> Game {
+        Game::from_board(Board::new())
+    }
+
+    pub fn from_board(board: Board) -> Game {
+        let mut game = Game {
+            board,
+            moves: Vec::new(),
+            history: Vec::new(),
+            positions: vec![board.hash()],
+            result: None,
+        };
+        game.update_result(board.outcome());
+        game
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// The position before the first move.
+    pub fn start_board(&self) -> Board {
+        let mut board = self.board;
+        for undo in self.history.iter().rev() {
+            board.unmake_move(undo);
+        }
+        board.is_running = true;
+        board
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        self.result
+    }
+
+    pub fn play(&mut self, m: &Move) -> Result<MoveOutcome, MoveError> {
+        if self.result.is_some() {
+            return Err(MoveError::GameOver);
+        }
+        let outcome = self.board.try_move(m)?;
+        self.moves.push(*m);
+        self.history.push(outcome.undo);
+        self.positions.push(self.board.hash());
+        self.update_result(outcome.outcome);
+        Ok(outcome)
+    }
+
+    /// Takes back the last move, reopening the game if it had ended.
+    pub fn undo(&mut self) -> Option<Move> {
+        let undo = self.history.pop()?;
+        self.board.unmake_move(&undo);
+        self.positions.pop();
+        self.board.is_running = true;
+        self.update_result(self.board.outcome());
+        self.moves.pop()
+    }
+
+    /// How many times the current position has occurred, counting this one.
+    pub fn repetitions(&self) -> usize {
+        self.positions.iter().filter(|&&p| p == self.board.hash()).count()
+    }
+
+    // `outcome` is the board's, passed in so moves don't generate it twice
+    fn update_result(&mut self, outcome: Option<Outcome>) {
+        self.result = match outcome {
+            Some(Outcome::Checkmate { winner }) => Some(GameResult::Checkmate { winner }),
+            Some(Outcome::Stalemate) => Some(GameResult::Stalemate),
+            None if self.repetitions() >= 3 => Some(GameResult::ThreefoldRepetition),
+            None if self.board.halfmove_clock() >= 100 => Some(GameResult::FiftyMoveRule),
+            None if self.board.has_insufficient_material() => Some(GameResult::InsufficientMaterial),
+            None => None,
+        };
+        if self.result.is_some() {
+            self.board.is_running = false;
+        }
+    }
+}
+
+impl Default for Game {
+    fn default() -> Game {
+        Game::new()
+    }
+}
@@ -1,16 +1,39 @@
+use std::convert::TryFrom;
 use std::fmt;
 use std::ops;
 use std::cmp;
 use serde::{ Serialize, Deserialize };
 
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub mod bitboard;
+mod dimensions;
+pub mod fen;
+mod game;
+mod notation;
+mod perft;
+pub mod pgn;
+mod rules;
+mod zobrist;
+
+use bitboard::{ Bitboard, color_index, kind_index, square_index, square_location, tables };
+
+pub use dimensions::Dimensions;
+pub use fen::FenError;
+pub use game::{ Game, GameResult };
+pub use notation::{ Notation, NotationError };
+pub use perft::{ perft, perft_divide };
+pub use pgn::{ GameRecord, PgnError, RecordMove };
+pub use rules::RuleSet;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Pieces {
     // boolean is has completed first move
     Pawn(bool),
-    Rook,
+    // boolean is has moved, which rules out castling with it
+    Rook(bool),
     Bishop,
     Queen,
-    King,
+    // boolean is has moved, which rules out castling
+    King(bool),
     Knight,
 }
 
@@ -20,7 +43,138 @@ pub enum Colors {
     White,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+impl Colors {
+    pub fn opponent(&self) -> Colors {
+        match self {
+            Colors::Black => Colors::White,
+            Colors::White => Colors::Black,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Outcome {
+    Checkmate { winner: Colors },
+    Stalemate,
+}
+
+/// Recorded after a pawn's double step so the opponent can capture it en passant
+/// on their next move.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EnPassant {
+    // the square the pawn skipped over
+    pub target: Location,
+    // where the pawn that double-stepped now stands
+    pub pawn: Location,
+}
+
+/// Returned by `Board::make_move`; holds everything needed to take the move back.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Undo {
+    played: Move,
+    // the piece as it stood before moving, with its first-move flag
+    moved: BoardState,
+    captured: BoardState,
+    // differs from the destination for en passant
+    captured_at: Location,
+    castling_rook: Option<Location>,
+    en_passant: Option<EnPassant>,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    hash: u64,
+}
+
+impl Undo {
+    pub fn played_move(&self) -> Move {
+        self.played
+    }
+}
+
+/// The result of a move accepted by `Board::try_move`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveOutcome {
+    pub captured: Option<(Colors, Pieces)>,
+    // the side now to move is in check
+    pub check: bool,
+    pub outcome: Option<Outcome>,
+    // hand to `Board::unmake_move` to take the move back
+    pub undo: Undo,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum MoveError {
+    NotYourTurn,
+    NoPieceAtSource,
+    // the move's piece field doesn't match the piece on the board
+    PieceMismatch,
+    IllegalDestination,
+    LeavesKingInCheck,
+    OutOfBounds,
+    // missing, unexpected or impossible promotion piece
+    InvalidPromotion,
+    GameOver,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            MoveError::NotYourTurn => "it is not that side's turn",
+            MoveError::NoPieceAtSource => "there is no piece on the starting square",
+            MoveError::PieceMismatch => "the piece on the starting square does not match the move",
+            MoveError::IllegalDestination => "the piece cannot move to that square",
+            MoveError::LeavesKingInCheck => "the move leaves the king in check",
+            MoveError::OutOfBounds => "the move is off the board",
+            MoveError::InvalidPromotion => "the promotion piece is missing or not allowed",
+            MoveError::GameOver => "the game is already over",
+        })
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+/// The pieces a pawn may become when it reaches a promotion square.
+pub const PROMOTION_PIECES: [Pieces; 4] = [Pieces::Queen, Pieces::Rook(true), Pieces::Bishop, Pieces::Knight];
+
+// offsets are listed for White; Black pawns use the negation
+const PAWN_OFFSETS: [(isize, isize, isize); 2] = [(0, 1, 0), (0, 0, 1)];
+const PAWN_CAPTURE_OFFSETS: [(isize, isize, isize); 6] = [
+    (-1, 1, 0), (1, 1, 0),
+    (1, 0, 1), (-1, 0, 1),
+    (-1, 1, 1), (1, 1, 1),
+];
+
+// the negation of each offset is also a valid knight jump
+const KNIGHT_OFFSETS: [(isize, isize, isize); 12] = [
+    // xy plane
+    (-1, 2, 0), (1, 2, 0), (2, -1, 0), (2, 1, 0),
+    // xz plane
+    (1, 0, 2), (-1, 0, 2), (2, 0, 1), (2, 0, -1),
+    // yz plane
+    (0, 1, 2), (0, -1, 2), (0, 2, 1), (0, 2, -1),
+];
+
+// sliding directions, one per line; the opposite direction is implied
+const ROOK_DIRECTIONS: [(isize, isize, isize); 3] = [(1, 0, 0), (0, 0, 1), (0, 1, 0)];
+const BISHOP_DIRECTIONS: [(isize, isize, isize); 6] = [
+    // xy plane
+    (-1, 1, 0), (1, 1, 0),
+    // xz plane
+    (-1, 0, 1), (1, 0, 1),
+    // yz plane
+    (0, -1, 1), (0, 1, 1),
+];
+// only used when the rule set allows triagonal moves
+const TRIAGONAL_DIRECTIONS: [(isize, isize, isize); 4] = [(1, 1, 1), (1, 1, -1), (1, -1, 1), (-1, 1, 1)];
+const QUEEN_DIRECTIONS: [(isize, isize, isize); 9] = [
+    // xy plane
+    (-1, 1, 0), (1, 1, 0), (1, 0, 0),
+    // xz plane
+    (-1, 0, 1), (0, 0, 1), (1, 0, 1),
+    // yz plane
+    (0, -1, 1), (0, 1, 1), (0, 1, 0),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum BoardState {
     Piece((Colors, Pieces)),
     Empty
@@ -36,10 +190,38 @@ pub struct Location {
 impl Location {
     pub fn new(x: isize, y: isize, z: isize) -> Location {
         Location {
-            x: x,
-            y: y,
-            z: z,
+            x,
+            y,
+            z,
+        }
+    }
+
+    /// Parses a square name: file letter, rank number and layer letter, like `c3B`.
+    pub fn from_square_name(name: &str) -> Option<Location> {
+        let mut chars = name.chars();
+        let (file, rank, layer) = (chars.next()?, chars.next()?, chars.next()?);
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) || !('A'..='H').contains(&layer) {
+            return None;
+        }
+        Some(Location::new(
+            file as isize - 'a' as isize,
+            rank as isize - '1' as isize,
+            layer as isize - 'A' as isize,
+        ))
+    }
+
+    /// The square's name, like `c3B` for (2, 2, 1), or None if it is off the cube.
+    pub fn square_name(&self) -> Option<String> {
+        let on_cube = |c: isize| (0..8).contains(&c);
+        if !(on_cube(self.x) && on_cube(self.y) && on_cube(self.z)) {
+            return None;
         }
+        Some(format!(
+            "{}{}{}",
+            (b'a' + self.x as u8) as char,
+            self.y + 1,
+            (b'A' + self.z as u8) as char,
+        ))
     }
 }
 
@@ -82,22 +264,38 @@ impl cmp::PartialEq for Location {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct Move {
     from: Location,
     to: Location,
     piece: (Colors, Pieces),
+    #[serde(default)]
+    promotion: Option<Pieces>,
 }
 
 impl Move {
     pub fn new(from: Location, to: Location, piece: (Colors, Pieces)) -> Move {
         Move {
-            from: from,
-            to: to,
-            piece: piece,
+            from,
+            to,
+            piece,
+            promotion: None,
         }
     }
 
+    pub fn new_promotion(from: Location, to: Location, piece: (Colors, Pieces), promotion: Pieces) -> Move {
+        Move {
+            from,
+            to,
+            piece,
+            promotion: Some(promotion),
+        }
+    }
+
+    pub fn promotion(&self) -> Option<Pieces> {
+        self.promotion
+    }
+
     pub fn from(&self) -> Location {
         self.from
     }
@@ -114,54 +312,170 @@ impl Move {
 impl From<(isize, isize, isize)> for Location {
     fn from((x, y, z): (isize, isize, isize)) -> Self {
         Location {
-            x: x,
-            y: y,
-            z: z,
+            x,
+            y,
+            z,
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(try_from = "RawBoard")]
 pub struct Board {
     board: [[[BoardState; 8]; 8]; 8],
     is_running: bool,
+    side_to_move: Colors,
+    // plies since the last capture or pawn move
+    halfmove_clock: u32,
+    // starts at 1 and increments after each Black move
+    fullmove_number: u32,
+    en_passant: Option<EnPassant>,
+    rules: RuleSet,
+    dimensions: Dimensions,
+    // Zobrist hash of the position, kept up to date by `set` and `make_move`
+    hash: u64,
+    // the squares of each colour's pieces by kind, kept in step with `board` by `set`
+    #[serde(skip)]
+    pieces: [[Bitboard; 6]; 2],
+    #[serde(skip)]
+    colors: [Bitboard; 2],
+    // the squares within `dimensions`; attack tables are masked with it
+    #[serde(skip)]
+    area: Bitboard,
+}
+
+// The serialized form of `Board`. The bitboards and hash are derived from the
+// squares on load rather than trusted.
+#[derive(Deserialize)]
+struct RawBoard {
+    board: [[[BoardState; 8]; 8]; 8],
+    is_running: bool,
+    side_to_move: Colors,
+    halfmove_clock: u32,
+    fullmove_number: u32,
+    en_passant: Option<EnPassant>,
+    #[serde(default)]
+    rules: RuleSet,
+    #[serde(default)]
+    dimensions: Dimensions,
+}
+
+impl TryFrom<RawBoard> for Board {
+    type Error = String;
+
+    // a board read from a peer is checked first, as a piece or en passant
+    // square off the board would panic once it is indexed
+    fn try_from(raw: RawBoard) -> Result<Board, String> {
+        let d = raw.dimensions;
+        let off_board = |l: Location| format!("({}, {}, {}) is off the board", l.x, l.y, l.z);
+        if let Some(EnPassant { target, pawn }) = raw.en_passant {
+            if let Some(&l) = [target, pawn].iter().find(|&&l| !d.contains(l)) {
+                return Err(format!("en passant square {}", off_board(l)));
+            }
+        }
+        for square in 0..bitboard::SQUARES {
+            let l = square_location(square);
+            if raw.board[l.x as usize][l.y as usize][l.z as usize] != BoardState::Empty && !d.contains(l) {
+                return Err(format!("piece on {}", off_board(l)));
+            }
+        }
+        Ok(Board::from_raw(raw))
+    }
+}
+
+impl Board {
+    // builds the board around squares already known to fit its dimensions
+    pub(crate) fn from_raw(raw: RawBoard) -> Board {
+        let mut board = Board {
+            board: raw.board,
+            is_running: raw.is_running,
+            side_to_move: raw.side_to_move,
+            halfmove_clock: raw.halfmove_clock,
+            fullmove_number: raw.fullmove_number,
+            en_passant: raw.en_passant,
+            rules: raw.rules,
+            dimensions: raw.dimensions,
+            hash: 0,
+            pieces: [[Bitboard::EMPTY; 6]; 2],
+            colors: [Bitboard::EMPTY; 2],
+            area: Bitboard::EMPTY,
+        };
+        board.rebuild();
+        board
+    }
 }
 
 impl Board {
     pub fn new() -> Board {
-        let mut b: [[[BoardState; 8]; 8]; 8] = [[[BoardState::Empty; 8]; 8]; 8];
-        for i in 0..8 {
-            // fill pawns
-            b[i][1][0] = BoardState::Piece((Colors::White, Pieces::Pawn(false)));
-            b[i][6][7] = BoardState::Piece((Colors::Black, Pieces::Pawn(false)));
+        Board::with_rules(RuleSet::default())
+    }
+
+    /// The starting position, played under `rules`.
+    pub fn with_rules(rules: RuleSet) -> Board {
+        Board::with_dimensions(Dimensions::STANDARD, rules)
+    }
+
+    /// The starting position for a board of the given size. White's pieces
+    /// start on the first ranks of the lowest layers and Black's mirror them
+    /// on the last ranks of the highest.
+    pub fn with_dimensions(dimensions: Dimensions, rules: RuleSet) -> Board {
+        let mut board = Board::empty(dimensions, rules);
+        let (ranks, layers) = (dimensions.ranks(), dimensions.layers());
+        for (z, rank) in dimensions.starting_ranks().iter().enumerate() {
+            let z = z as isize;
+            for (x, c) in rank.chars().enumerate() {
+                let x = x as isize;
+                let piece = match c {
+                    'R' => Pieces::Rook(false),
+                    'N' => Pieces::Knight,
+                    'B' => Pieces::Bishop,
+                    'Q' => Pieces::Queen,
+                    _ => Pieces::King(false),
+                };
+                board.set(Location::new(x, 0, z), BoardState::Piece((Colors::White, piece)));
+                board.set(Location::new(x, ranks - 1, layers - 1 - z), BoardState::Piece((Colors::Black, piece)));
+                // pawns need a rank of their own on each side
+                if ranks >= 4 {
+                    board.set(Location::new(x, 1, z), BoardState::Piece((Colors::White, Pieces::Pawn(false))));
+                    board.set(Location::new(x, ranks - 2, layers - 1 - z), BoardState::Piece((Colors::Black, Pieces::Pawn(false))));
+                }
+            }
         }
-        {
-            use Pieces::*;
-            use BoardState::*;
-            use Colors::*;
-            b[0][0][0] = Piece((White, Rook));
-            b[7][0][0] = Piece((White, Rook));
-            b[1][0][0] = Piece((White, Knight));
-            b[6][0][0] = Piece((White, Knight));
-            b[2][0][0] = Piece((White, Bishop));
-            b[5][0][0] = Piece((White, Bishop));
-            b[3][0][0] = Piece((White, Queen));
-            b[4][0][0] = Piece((White, King));
-
-            b[0][7][7] = Piece((Black, Rook));
-            b[7][7][7] = Piece((Black, Rook));
-            b[1][7][7] = Piece((Black, Knight));
-            b[6][7][7] = Piece((Black, Knight));
-            b[2][7][7] = Piece((Black, Bishop));
-            b[5][7][7] = Piece((Black, Bishop));
-            b[3][7][7] = Piece((Black, Queen));
-            b[4][7][7] = Piece((Black, King));
-        }
-
-        Board {
-            board: b,
+        board
+    }
+
+    /// The starting position for a named board size, played under the rule
+    /// preset of the same name if there is one and the default rules otherwise.
+    pub fn preset(name: &str) -> Option<Board> {
+        Dimensions::preset(name).map(|dims| Board::with_dimensions(dims, RuleSet::preset(name).unwrap_or_default()))
+    }
+
+    /// A board with no pieces on it and White to move.
+    pub fn empty(dimensions: Dimensions, rules: RuleSet) -> Board {
+        Board::from_raw(RawBoard {
+            board: [[[BoardState::Empty; 8]; 8]; 8],
             is_running: true,
+            side_to_move: Colors::White,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+            en_passant: None,
+            rules,
+            dimensions,
+        })
+    }
+
+    // recomputes the bitboards and hash from the squares
+    fn rebuild(&mut self) {
+        self.area = self.dimensions.area();
+        self.pieces = [[Bitboard::EMPTY; 6]; 2];
+        self.colors = [Bitboard::EMPTY; 2];
+        for square in 0..bitboard::SQUARES {
+            if let BoardState::Piece((c, p)) = self.at(square_location(square)) {
+                self.pieces[color_index(c)][kind_index(p)].insert(square);
+                self.colors[color_index(c)].insert(square);
+            }
         }
+        self.hash = self.compute_hash();
     }
 
     pub fn at<T: Into<Location>>(&self, l: T) -> BoardState {
@@ -170,14 +484,89 @@ impl Board {
     }
 
     pub fn set(&mut self, l: Location, s: BoardState) {
-        self.board[l.x as usize][l.y as usize][l.z as usize] = s;
+        let index = square_index(l);
+        let square = &mut self.board[l.x as usize][l.y as usize][l.z as usize];
+        self.hash ^= zobrist::square_key(l, *square) ^ zobrist::square_key(l, s);
+        if let BoardState::Piece((c, p)) = *square {
+            self.pieces[color_index(c)][kind_index(p)].remove(index);
+            self.colors[color_index(c)].remove(index);
+        }
+        if let BoardState::Piece((c, p)) = s {
+            self.pieces[color_index(c)][kind_index(p)].insert(index);
+            self.colors[color_index(c)].insert(index);
+        }
+        *square = s;
+    }
+
+    /// The squares holding pieces of colour `c` and the same kind as `p`,
+    /// whatever their first-move flags.
+    pub fn piece_set(&self, c: Colors, p: Pieces) -> Bitboard {
+        self.pieces[color_index(c)][kind_index(p)]
+    }
+
+    /// The squares holding pieces of colour `c`.
+    pub fn color_set(&self, c: Colors) -> Bitboard {
+        self.colors[color_index(c)]
+    }
+
+    pub fn occupied(&self) -> Bitboard {
+        self.colors[0] | self.colors[1]
+    }
+
+    // moves to each square of `targets` that isn't held by the mover's own pieces
+    fn moves_to(&self, l: Location, piece: (Colors, Pieces), targets: Bitboard) -> Vec<Move> {
+        (targets & self.area & !self.color_set(piece.0))
+            .squares()
+            .map(|t| Move::new(l, square_location(t), piece))
+            .collect()
+    }
+
+    /// The incrementally updated Zobrist hash of the pieces (with their
+    /// first-move flags), the side to move and the en passant target.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// Recomputes the hash from scratch; always equal to `hash()`.
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    let l = Location::new(x, y, z);
+                    hash ^= zobrist::square_key(l, self.at(l));
+                }
+            }
+        }
+        if self.side_to_move == Colors::Black {
+            hash ^= zobrist::BLACK_TO_MOVE;
+        }
+        if let Some(ep) = self.en_passant {
+            hash ^= zobrist::en_passant_key(ep.target);
+        }
+        hash
+    }
+
+    fn location_is_valid(&self, l: Location) -> bool {
+        self.dimensions.contains(l)
     }
 
-    fn location_is_valid(l: Location) -> bool {
-        if l.x < 0 || l.x > 7 || l.y < 0 || l.y > 7 || l.z < 0 || l.z > 7 {
-            false
+    /// Pawns promote on the far edge of the board: the last rank of the last
+    /// layer from their own side, where they have no forward moves left.
+    pub fn is_promotion_square(&self, l: Location, c: Colors) -> bool {
+        match c {
+            Colors::White => l.y == self.dimensions.ranks() - 1 && l.z == self.dimensions.layers() - 1,
+            Colors::Black => l.y == 0 && l.z == 0,
+        }
+    }
+
+    fn push_pawn_move(&self, moves: &mut Vec<Move>, from: Location, to: Location, piece: (Colors, Pieces)) {
+        if self.is_promotion_square(to, piece.0) {
+            for &p in PROMOTION_PIECES.iter() {
+                moves.push(Move::new_promotion(from, to, piece, p));
+            }
         } else {
-            true
+            moves.push(Move::new(from, to, piece));
         }
     }
 
@@ -187,17 +576,7 @@ impl Board {
             BoardState::Piece(p) => p,
         };
 
-        let mut offsets: Vec<Location> = Vec::new();
-        offsets.push(Location::new(0, 1, 0));
-        offsets.push(Location::new(0, 0, 1));
-        
-        let mut capture_offsets: Vec<Location> = Vec::new();
-        capture_offsets.push(Location::new(-1, 1, 0));
-        capture_offsets.push(Location::new(1, 1, 0));
-        capture_offsets.push(Location::new(1, 0, 1));
-        capture_offsets.push(Location::new(-1, 0, 1));
-        capture_offsets.push(Location::new(-1, 1, 1));
-        capture_offsets.push(Location::new(1, 1, 1));
+        let mut offsets: Vec<Location> = PAWN_OFFSETS.iter().map(|&o| o.into()).collect();
 
         let mut max_distance = 1;
 
@@ -207,11 +586,8 @@ impl Board {
             }
         }
         if let (Colors::Black, Pieces::Pawn(b)) = piece {
-            for i in 0..offsets.len() {
-                offsets[i] *= -1;
-            }
-            for i in 0..capture_offsets.len() {
-                capture_offsets[i] *= -1;
+            for o in offsets.iter_mut() {
+                *o *= -1;
             }
             if !b {
                 max_distance = 2;
@@ -222,9 +598,9 @@ impl Board {
         for o in offsets {
             let mut loc = l + o;
             let mut distance = 1;
-            while Board::location_is_valid(loc) && distance <= max_distance {
+            while self.location_is_valid(loc) && distance <= max_distance {
                 if let BoardState::Empty = self.at(loc) {
-                    moves.push(Move::new(l, loc, piece));
+                    self.push_pawn_move(&mut moves, l, loc, piece);
                     loc = loc + o;
                     distance += 1;
                 } else {
@@ -232,251 +608,490 @@ impl Board {
                 }
             }
         }
-        for cap in capture_offsets {
-            if Board::location_is_valid(l + cap) {
-                match piece {
-                    (Colors::Black, _) => {
-                        if let BoardState::Piece((Colors::White, _)) = self.at(l + cap) {
-                            moves.push(Move::new(l, l + cap, piece));
-                        }
-                    },
-                    (Colors::White, _) => {
-                        if let BoardState::Piece((Colors::Black, _)) = self.at(l + cap) {
-                            moves.push(Move::new(l, l + cap, piece));
-                        }
+        let captures = tables().pawn(piece.0, square_index(l)) & self.area;
+        for target in (captures & self.color_set(piece.0.opponent())).squares() {
+            self.push_pawn_move(&mut moves, l, square_location(target), piece);
+        }
+        if let Some(ep) = self.en_passant {
+            if captures.contains(square_index(ep.target)) {
+                if let BoardState::Piece((color, Pieces::Pawn(_))) = self.at(ep.pawn) {
+                    if color != piece.0 {
+                        self.push_pawn_move(&mut moves, l, ep.target, piece);
                     }
                 }
             }
         }
-        return moves;
+        moves
     }
 
     fn knight_moves(&self, l: Location) -> Vec<Move> {
-        let mut offsets: Vec<Location> = Vec::new();
+        match self.at(l) {
+            BoardState::Piece((c, _)) => self.moves_to(l, (c, Pieces::Knight), tables().knight(square_index(l))),
+            BoardState::Empty => vec![],
+        }
+    }
 
-        // xy plane
-        offsets.push((-1, 2, 0).into());
-        offsets.push((1, 2, 0).into());
-        offsets.push((2, -1, 0).into());
-        offsets.push((2, 1, 0).into());
+    fn queen_moves(&self, l: Location) -> Vec<Move> {
+        if self.rules.triagonal_queens {
+            self.queen_rook_bishop_moves(l, &[&QUEEN_DIRECTIONS[..], &TRIAGONAL_DIRECTIONS[..]].concat())
+        } else {
+            self.queen_rook_bishop_moves(l, &QUEEN_DIRECTIONS)
+        }
+    }
 
-        // xz plane
-        offsets.push((1, 0, 2).into());
-        offsets.push((-1, 0, 2).into());
-        offsets.push((2, 0, 1).into());
-        offsets.push((2, 0, -1).into());
+    fn bishop_moves(&self, l: Location) -> Vec<Move> {
+        if self.rules.triagonal_bishops {
+            self.queen_rook_bishop_moves(l, &[&BISHOP_DIRECTIONS[..], &TRIAGONAL_DIRECTIONS[..]].concat())
+        } else {
+            self.queen_rook_bishop_moves(l, &BISHOP_DIRECTIONS)
+        }
+    }
+
+    fn rook_moves(&self, l: Location) -> Vec<Move> {
+        self.queen_rook_bishop_moves(l, &ROOK_DIRECTIONS)
+    }
+
+    // the squares a slider on `l` reaches along each direction and its opposite
+    fn slider_attacks(&self, l: Location, directions: &[(isize, isize, isize)]) -> Bitboard {
+        let t = tables();
+        let square = square_index(l);
+        let occupied = self.occupied();
+        let mut attacks = Bitboard::EMPTY;
+        for &(dx, dy, dz) in directions {
+            attacks |= t.slide(square, (dx, dy, dz), occupied);
+            attacks |= t.slide(square, (-dx, -dy, -dz), occupied);
+        }
+        attacks
+    }
+
+    fn queen_rook_bishop_moves(&self, l: Location, directions: &[(isize, isize, isize)]) -> Vec<Move> {
+        let piece = match self.at(l) {
+            BoardState::Piece(p) => p,
+            BoardState::Empty => return vec![],
+        };
+        self.moves_to(l, piece, self.slider_attacks(l, directions))
+    }
+
+    fn king_moves(&self, l: Location) -> Vec<Move> {
+        let piece = match self.at(l) {
+            BoardState::Piece(p) => p,
+            BoardState::Empty => return vec![],
+        };
 
-        // yz plane
-        offsets.push((0, 1, 2).into());
-        offsets.push((0, -1, 2).into());
-        offsets.push((0, 2, 1).into());
-        offsets.push((0, 2, -1).into());
+        let mut targets = tables().king(square_index(l));
+        if self.rules.triagonal_kings {
+            targets |= tables().king_triagonal(square_index(l));
+        }
+        let mut moves = self.moves_to(l, piece, targets);
 
-        for i in 0..12 {
-            offsets.push(offsets[i] * -1);
+        if let (color, Pieces::King(false)) = piece {
+            moves.append(&mut self.castling_moves(l, color));
         }
 
+        moves
+    }
+
+    // The king moves two squares along its rank towards an unmoved rook, which
+    // jumps over it. Every square between them must be empty, and the king may
+    // not start on, pass through or land on a square attacked anywhere in the cube.
+    fn castling_moves(&self, l: Location, color: Colors) -> Vec<Move> {
         let mut moves = Vec::new();
-        if let BoardState::Piece((Colors::Black, _)) = self.at(l) {
-            for o in offsets {
-                if Board::location_is_valid(l+o) {
-                    match self.at(l + o) {
-                        BoardState::Piece((Colors::Black, _)) => {},
-                        _ => { moves.push(Move::new(l, l+o, (Colors::Black, Pieces::Knight))) }
-                    }
-                }
-            }
-        } else if let BoardState::Piece((Colors::White, _)) = self.at(l) {
-            for o in offsets {
-                if Board::location_is_valid(l + o) {
-                    match self.at(l + o) {
-                        BoardState::Piece((Colors::White, _)) => {},
-                        _ => { moves.push(Move::new(l, l+o, (Colors::White, Pieces::Knight))) }
+        if self.is_square_attacked(l, color.opponent()) {
+            return moves;
+        }
+        for &dx in [1, -1].iter() {
+            let step = Location::new(dx, 0, 0);
+            let mut loc = l + step;
+            let mut distance = 1;
+            while self.location_is_valid(loc) {
+                if let BoardState::Piece(p) = self.at(loc) {
+                    if p == (color, Pieces::Rook(false))
+                        && distance >= 3
+                        && !self.is_square_attacked(l + step, color.opponent())
+                        && !self.is_square_attacked(l + step * 2, color.opponent()) {
+                        moves.push(Move::new(l, l + step * 2, (color, Pieces::King(false))));
                     }
+                    break;
                 }
+                loc = loc + step;
+                distance += 1;
             }
         }
-
         moves
     }
 
-    fn queen_moves(&self, l: Location) -> Vec<Move> {
-        let mut offsets: Vec<Location> = Vec::new();
-        // xy plane
-        offsets.push((-1, 1, 0).into());
-        offsets.push((1, 1, 0).into());
-        offsets.push((1, 0, 0).into());
-        
-        // xz plane
-        offsets.push((-1, 0, 1).into());
-        offsets.push((0, 0, 1).into());
-        offsets.push((1, 0, 1).into());
-
-        // yz plane
-        offsets.push((0, -1, 1).into());
-        offsets.push((0, 1, 1).into());
-        offsets.push((0, 1, 0).into());
+    pub fn piece_moves(&self, l: Location) -> Vec<Move> {
+        if let BoardState::Empty = self.at(l) {
+            vec![]
+        } else if let BoardState::Piece(p) = self.at(l) {
+            use Pieces::*;
+            match p {
+                (_, Pawn(_)) => self.pawn_moves(l),
+                (_, Knight) => self.knight_moves(l),
+                (_, Queen) => self.queen_moves(l),
+                (_, Bishop) => self.bishop_moves(l),
+                (_, Rook(_)) => self.rook_moves(l),
+                (_, King(_)) => self.king_moves(l),
+            }
+        } else {
+            vec![]
+        }
+    }
 
-        self.queen_rook_bishop_moves(l, offsets)
+    fn find_king(&self, c: Colors) -> Option<Location> {
+        self.piece_set(c, Pieces::King(false)).first().map(square_location)
     }
 
-    fn bishop_moves(&self, l: Location) -> Vec<Move> {
-        let mut offsets: Vec<Location> = Vec::new();
-        // xy plane
-        offsets.push((-1, 1, 0).into());
-        offsets.push((1, 1, 0).into());
-        
-        // xz plane
-        offsets.push((-1, 0, 1).into());
-        offsets.push((1, 0, 1).into());
+    /// Returns true if any piece of colour `by` attacks `l`, whether or not `l` is occupied.
+    pub fn is_square_attacked(&self, l: Location, by: Colors) -> bool {
+        let t = tables();
+        let square = square_index(l);
+        let attackers = |p: Pieces| self.piece_set(by, p);
 
-        // yz plane
-        offsets.push((0, -1, 1).into());
-        offsets.push((0, 1, 1).into());
+        // a pawn of `by` attacks `l` from wherever an opposing pawn on `l` would attack
+        if !(t.knight(square) & attackers(Pieces::Knight)).is_empty()
+            || !(t.king(square) & attackers(Pieces::King(false))).is_empty()
+            || !(t.pawn(by.opponent(), square) & attackers(Pieces::Pawn(false))).is_empty() {
+            return true;
+        }
+        if self.rules.triagonal_kings && !(t.king_triagonal(square) & attackers(Pieces::King(false))).is_empty() {
+            return true;
+        }
 
-        self.queen_rook_bishop_moves(l, offsets)
+        let queens = attackers(Pieces::Queen);
+        let rooks = attackers(Pieces::Rook(false)) | queens;
+        let bishops = attackers(Pieces::Bishop) | queens;
+        let occupied = self.occupied();
+        let slides = |directions: &[(isize, isize, isize)], sliders: Bitboard| {
+            directions.iter().any(|&(dx, dy, dz)| {
+                [(dx, dy, dz), (-dx, -dy, -dz)].iter().any(|&d| {
+                    // only look for the blocker when a slider is somewhere on the ray
+                    !(t.ray(square, d) & sliders).is_empty()
+                        && !(t.slide(square, d, occupied) & sliders).is_empty()
+                })
+            })
+        };
+        let mut triagonal = Bitboard::EMPTY;
+        if self.rules.triagonal_bishops {
+            triagonal |= attackers(Pieces::Bishop);
+        }
+        if self.rules.triagonal_queens {
+            triagonal |= queens;
+        }
+        slides(&ROOK_DIRECTIONS, rooks)
+            || slides(&BISHOP_DIRECTIONS, bishops)
+            || (!triagonal.is_empty() && slides(&TRIAGONAL_DIRECTIONS, triagonal))
     }
 
-    fn rook_moves(&self, l: Location) -> Vec<Move> {
-        let mut offsets: Vec<Location> = Vec::new();
-        // xy plane
-        offsets.push((1, 0, 0).into());
-        
-        // xz plane
-        offsets.push((0, 0, 1).into());
-
-        // yz plane
-        offsets.push((0, 1, 0).into());
+    pub fn is_in_check(&self, c: Colors) -> bool {
+        match self.find_king(c) {
+            Some(king) => self.is_square_attacked(king, c.opponent()),
+            None => false,
+        }
+    }
 
-        self.queen_rook_bishop_moves(l, offsets)
+    /// Moves for the piece at `l` that do not leave its own king in check.
+    pub fn legal_piece_moves(&self, l: Location) -> Vec<Move> {
+        self.without_self_check(self.piece_moves(l))
     }
 
-    fn queen_rook_bishop_moves(&self, l: Location, mut offsets: Vec<Location>) -> Vec<Move> {
-        let len = offsets.len();
-        for i in 0..len {
-            offsets.push(offsets[i] * -1);
+    pub fn legal_moves(&self, c: Colors) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for square in self.color_set(c).squares() {
+            moves.append(&mut self.piece_moves(square_location(square)));
         }
+        self.without_self_check(moves)
+    }
 
-        let piece = match self.at(l) {
-            BoardState::Piece(p) => p,
-            BoardState::Empty => (Colors::White, Pieces::Pawn(false)),
+    fn without_self_check(&self, mut moves: Vec<Move>) -> Vec<Move> {
+        let color = match moves.first() {
+            Some(m) => m.piece().0,
+            None => return moves,
         };
+        // when not in check, only the king, en passant captures and pieces on a
+        // line through the king can expose it
+        let exposed = match self.find_king(color) {
+            Some(king) if !self.is_in_check(color) => tables().lines(square_index(king)),
+            _ => !Bitboard::EMPTY,
+        };
+        let mut scratch = *self;
+        moves.retain(|m| {
+            if !exposed.contains(square_index(m.from()))
+                && !matches!(m.piece().1, Pieces::King(_))
+                && self.captured_square(m) == m.to() {
+                return true;
+            }
+            let undo = scratch.make_move(m);
+            let legal = !scratch.is_in_check(m.piece().0);
+            scratch.unmake_move(&undo);
+            legal
+        });
+        moves
+    }
 
-        let mut moves: Vec<Move> = Vec::new();
+    /// Checkmate or stalemate if the side to move has no legal moves, otherwise None.
+    pub fn outcome(&self) -> Option<Outcome> {
+        let c = self.side_to_move;
+        if !self.legal_moves(c).is_empty() {
+            None
+        } else if self.is_in_check(c) {
+            Some(Outcome::Checkmate { winner: c.opponent() })
+        } else {
+            Some(Outcome::Stalemate)
+        }
+    }
 
-        for o in offsets {
-            let mut loc = l + o;
-            while Self::location_is_valid(loc) {
-                if let BoardState::Empty = self.at(loc) {
-                    moves.push(Move::new(l, loc, piece));
-                }
-                if let BoardState::Piece((color, _)) = self.at(loc) {
-                    if color == piece.0 {
-                        break;
-                    } else {
-                        moves.push(Move::new(l, loc, piece));
-                        break;
+    /// True if neither side can possibly checkmate: only kings remain besides
+    /// a single knight or bishop, or bishops that all stand on one colour of
+    /// square and can't leave it because triagonal bishop moves are off.
+    pub fn has_insufficient_material(&self) -> bool {
+        let mut knights = 0;
+        let mut bishop_parities = Vec::new();
+        for x in 0..8 {
+            for y in 0..8 {
+                for z in 0..8 {
+                    match self.at((x, y, z)) {
+                        BoardState::Piece((_, Pieces::King(_))) | BoardState::Empty => {},
+                        BoardState::Piece((_, Pieces::Knight)) => knights += 1,
+                        // bishops never change the parity of x + y + z
+                        BoardState::Piece((_, Pieces::Bishop)) => bishop_parities.push((x + y + z) % 2),
+                        BoardState::Piece(_) => return false,
                     }
                 }
-                loc = loc + o;
             }
         }
-
-        moves
+        match (knights, bishop_parities.len()) {
+            (0, 0) | (1, 0) | (0, 1) => true,
+            (0, _) if !self.rules.triagonal_bishops => bishop_parities.iter().all(|&p| p == bishop_parities[0]),
+            _ => false,
+        }
     }
 
-    fn king_moves(&self, l: Location) -> Vec<Move> {
-        let mut offsets: Vec<Location> = Vec::new();
-        // xy plane
-        offsets.push((-1, 1, 0).into());
-        offsets.push((1, 1, 0).into());
-        offsets.push((1, 0, 0).into());
-        
-        // xz plane
-        offsets.push((-1, 0, 1).into());
-        offsets.push((0, 0, 1).into());
-        offsets.push((1, 0, 1).into());
-
-        // yz plane
-        offsets.push((0, -1, 1).into());
-        offsets.push((0, 1, 1).into());
-        offsets.push((0, 1, 0).into());
-
-        let len = offsets.len();
-        for i in 0..len {
-            offsets.push(offsets[i] * -1);
+    /// Checks `m` against the position and plays it if it is legal.
+    pub fn try_move(&mut self, m: &Move) -> Result<MoveOutcome, MoveError> {
+        if !self.location_is_valid(m.from()) || !self.location_is_valid(m.to()) {
+            return Err(MoveError::OutOfBounds);
+        }
+        let piece = match self.at(m.from()) {
+            BoardState::Piece(p) => p,
+            BoardState::Empty => return Err(MoveError::NoPieceAtSource),
+        };
+        if piece.0 != self.side_to_move {
+            return Err(MoveError::NotYourTurn);
+        }
+        if piece != m.piece() {
+            return Err(MoveError::PieceMismatch);
+        }
+        let promotes = matches!(piece.1, Pieces::Pawn(_)) && self.is_promotion_square(m.to(), piece.0);
+        match m.promotion() {
+            None if promotes => return Err(MoveError::InvalidPromotion),
+            Some(_) if !promotes => return Err(MoveError::InvalidPromotion),
+            Some(p) if !PROMOTION_PIECES.contains(&p) => return Err(MoveError::InvalidPromotion),
+            _ => {}
+        }
+        if !self.piece_moves(m.from()).contains(m) {
+            return Err(MoveError::IllegalDestination);
+        }
+        let undo = self.make_move(m);
+        if self.is_in_check(piece.0) {
+            self.unmake_move(&undo);
+            return Err(MoveError::LeavesKingInCheck);
         }
 
-        let mut moves: Vec<Move> = Vec::new();
+        let captured = match undo.captured {
+            BoardState::Piece(p) => Some(p),
+            BoardState::Empty => None,
+        };
+        Ok(MoveOutcome {
+            captured,
+            check: self.is_in_check(self.side_to_move),
+            outcome: self.outcome(),
+            undo,
+        })
+    }
 
-        let piece = match self.at(l) {
-            BoardState::Piece(p) => p,
-            BoardState::Empty => (Colors::White, Pieces::Pawn(false)),
+    /// Plays `m` and passes the turn. Returns false without changing the board
+    /// if the piece being moved does not belong to the side to move.
+    pub fn execute_move(&mut self, m: &Move) -> bool {
+        match self.at(m.from()) {
+            BoardState::Piece((color, _)) if color == self.side_to_move => {},
+            _ => return false,
+        }
+        self.make_move(m);
+        true
+    }
+
+    /// Plays `m` without any checks and returns the token that takes it back
+    /// with `unmake_move`. The move should come from this position's move list.
+    pub fn make_move(&mut self, m: &Move) -> Undo {
+        let captured_at = self.captured_square(m);
+        let undo = Undo {
+            played: *m,
+            moved: self.at(m.from()),
+            captured: self.at(captured_at),
+            captured_at,
+            castling_rook: self.castling_rook(m),
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            fullmove_number: self.fullmove_number,
+            hash: self.hash,
         };
 
-        for o in offsets {
-            if Self::location_is_valid(l+o) {
-                if let BoardState::Empty = self.at(l+o) {
-                    moves.push(Move::new(l, l+o, piece));
-                }
-                if let BoardState::Piece((color, _)) = self.at(l+o) {
-                    if color != piece.0 {
-                        moves.push(Move::new(l, l+o, piece));
-                    }
-                }
+        let resets_clock = matches!(m.piece.1, Pieces::Pawn(_))
+            || matches!(undo.captured, BoardState::Piece(_));
+        self.apply_move(m);
+
+        if let Some(ep) = self.en_passant {
+            self.hash ^= zobrist::en_passant_key(ep.target);
+        }
+        self.en_passant = None;
+        if let Pieces::Pawn(_) = m.piece.1 {
+            let (dy, dz) = (m.to().y - m.from().y, m.to().z - m.from().z);
+            if m.to().x == m.from().x && (dy.abs() == 2 || dz.abs() == 2) {
+                let target = Location::new(m.from().x, m.from().y + dy / 2, m.from().z + dz / 2);
+                self.en_passant = Some(EnPassant { target, pawn: m.to() });
+                self.hash ^= zobrist::en_passant_key(target);
             }
         }
 
-        moves
+        if resets_clock {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if self.side_to_move == Colors::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = self.side_to_move.opponent();
+        self.hash ^= zobrist::BLACK_TO_MOVE;
+        undo
     }
 
-    pub fn piece_moves(&self, l: Location) -> Vec<Move> {
-        if let BoardState::Empty = self.at(l) {
-            vec![]
-        } else if let BoardState::Piece(p) = self.at(l) {
-            use Pieces::*;
-            match p {
-                (_, Pawn(_)) => self.pawn_moves(l),
-                (_, Knight) => self.knight_moves(l),
-                (_, Queen) => self.queen_moves(l),
-                (_, Bishop) => self.bishop_moves(l),
-                (_, Rook) => self.rook_moves(l),
-                (_, King) => self.king_moves(l),
+    /// Restores the position from before the move `undo` was returned for. Moves
+    /// must be unmade in the reverse order they were made.
+    pub fn unmake_move(&mut self, undo: &Undo) {
+        let m = undo.played;
+        if let Some(rook) = undo.castling_rook {
+            let step = Location::new((m.to().x - m.from().x).signum(), 0, 0);
+            self.set(m.from() + step, BoardState::Empty);
+            self.set(rook, BoardState::Piece((m.piece.0, Pieces::Rook(false))));
+        }
+        self.set(m.to(), BoardState::Empty);
+        self.set(undo.captured_at, undo.captured);
+        self.set(m.from(), undo.moved);
+
+        self.en_passant = undo.en_passant;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.fullmove_number = undo.fullmove_number;
+        self.side_to_move = m.piece.0;
+        self.hash = undo.hash;
+    }
+
+    // the square of the piece `m` captures, which differs from `m.to()` for en passant.
+    // Every pawn capture changes file and no pawn push does.
+    fn captured_square(&self, m: &Move) -> Location {
+        match (m.piece.1, self.en_passant) {
+            (Pieces::Pawn(_), Some(ep)) if ep.target == m.to() && m.to().x != m.from().x => ep.pawn,
+            _ => m.to(),
+        }
+    }
+
+    // for a castling move, the square of the rook that jumps over the king:
+    // the first piece beyond the king's destination
+    fn castling_rook(&self, m: &Move) -> Option<Location> {
+        if let (_, Pieces::King(false)) = m.piece {
+            let dx = m.to().x - m.from().x;
+            if dx.abs() == 2 && m.to().y == m.from().y && m.to().z == m.from().z {
+                let step = Location::new(dx.signum(), 0, 0);
+                let mut rook = m.to() + step;
+                while self.location_is_valid(rook) && self.at(rook) == BoardState::Empty {
+                    rook = rook + step;
+                }
+                return Some(rook);
             }
-        } else {
-            vec![]
         }
+        None
     }
 
-    pub fn execute_move(&mut self, m: &Move) {
+    // moves the pieces without touching any of the turn state
+    fn apply_move(&mut self, m: &Move) {
+        let captured = self.captured_square(m);
+        if captured != m.to() {
+            self.set(captured, BoardState::Empty);
+        }
+
+        if let Some(rook) = self.castling_rook(m) {
+            let step = Location::new((m.to().x - m.from().x).signum(), 0, 0);
+            self.set(rook, BoardState::Empty);
+            self.set(m.from() + step, BoardState::Piece((m.piece.0, Pieces::Rook(true))));
+        }
+
         self.set(m.to(), self.at(m.from()));
         self.set(m.from(), BoardState::Empty);
         match m.piece {
-            (Colors::Black, Pieces::Pawn(false)) => self.set(m.to(), BoardState::Piece((Colors::Black, Pieces::Pawn(true)))),
-            (Colors::White, Pieces::Pawn(false)) => self.set(m.to(), BoardState::Piece((Colors::White, Pieces::Pawn(true)))),
+            (c, Pieces::Pawn(false)) => self.set(m.to(), BoardState::Piece((c, Pieces::Pawn(true)))),
+            (c, Pieces::Rook(false)) => self.set(m.to(), BoardState::Piece((c, Pieces::Rook(true)))),
+            (c, Pieces::King(false)) => self.set(m.to(), BoardState::Piece((c, Pieces::King(true)))),
             _ => {}
         }
+        if let Some(p) = m.promotion() {
+            self.set(m.to(), BoardState::Piece((m.piece.0, p)));
+        }
     }
 
     pub fn update_board(&mut self, other: &Board) {
-        self.board = other.board;
-        self.is_running = other.is_running;
+        *self = *other;
     }
 
     pub fn is_running(&self) -> bool {
         self.is_running
     }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    pub fn dimensions(&self) -> Dimensions {
+        self.dimensions
+    }
+
+    /// Plays the current position on under different rules.
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.rules = rules;
+    }
+
+    pub fn side_to_move(&self) -> Colors {
+        self.side_to_move
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    pub fn en_passant(&self) -> Option<EnPassant> {
+        self.en_passant
+    }
+}
+
+impl Default for Board {
+    fn default() -> Board {
+        Board::new()
+    }
 }
 
 impl Pieces {
     pub fn character(&self) -> char {
         match self {
             Pieces::Bishop => 'B',
-            Pieces::King => 'K',
+            Pieces::King(_) => 'K',
             Pieces::Knight => 'N',
             Pieces::Pawn(_) => 'P',
             Pieces::Queen => 'Q',
-            Pieces::Rook => 'R',
+            Pieces::Rook(_) => 'R',
         }
     }
 }
@@ -499,13 +1114,14 @@ impl fmt::Display for BoardState {
 
 impl fmt::Display for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for z in 0..8 {
-            write!(f, "Board {}: \n", z)?;
-            for y in (0..8).rev() {
-                for x in 0..8 {
+        let d = self.dimensions;
+        for z in 0..d.layers() as usize {
+            writeln!(f, "Board {}: ", z)?;
+            for y in (0..d.ranks() as usize).rev() {
+                for x in 0..d.files() as usize {
                     write!(f, "{}", self.board[x][y][z])?;
                 }
-                write!(f, "\n")?;
+                writeln!(f)?;
             }
         }
         Ok(())
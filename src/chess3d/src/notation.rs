@@ -0,0 +1,239 @@
+//! Algebraic move notation. Squares are named by file letter, rank number and
+//! layer letter, like `c3B`. A move is written with the piece letter (none for
+//! pawns), the square it leaves, `-` or `x` for a capture, the square it
+//! reaches, `=Q` for a promotion and `+` or `#` for check or checkmate, like
+//! `Nb1A-b3B` or `c7H-c8H=Q`. Castling towards the higher files is `O-O` and
+//! towards the lower files `O-O-O`.
+//!
+//! The short form leaves out as much of the starting square as it can while
+//! staying unambiguous, like `Nb3B`, or `Nbxc3A` when a knight on d1A could
+//! also take on c3A. Pawn moves always keep their file when any of the
+//! starting square is needed, so they never start with a capital letter.
+
+use std::fmt;
+use std::mem;
+
+use crate::{ Board, BoardState, Location, Move, Outcome, Pieces };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notation {
+    // the starting square as written only when needed, like `Nb3B`
+    Short,
+    // the full starting square, like `Nb1A-b3B`
+    Long,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NotationError {
+    // the text isn't a move in either form
+    Malformed(String),
+    // no legal move matches
+    NoSuchMove(String),
+    // more than one legal move matches
+    Ambiguous(String),
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::Malformed(s) => write!(f, "'{}' is not a move", s),
+            NotationError::NoSuchMove(s) => write!(f, "no legal move matches '{}'", s),
+            NotationError::Ambiguous(s) => write!(f, "'{}' could be more than one move", s),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+// which parts of the starting square to write: file, rank and layer
+type Disambiguation = (bool, bool, bool);
+
+// from fewest parts to most; the first that tells the moves apart is used
+const DISAMBIGUATIONS: [Disambiguation; 7] = [
+    (true, false, false), (false, true, false), (false, false, true),
+    (true, true, false), (true, false, true), (false, true, true),
+    (true, true, true),
+];
+
+fn piece_letter(p: Pieces) -> Option<Pieces> {
+    match p {
+        Pieces::Pawn(_) => None,
+        p => Some(p),
+    }
+}
+
+fn same_kind(a: Pieces, b: Pieces) -> bool {
+    mem::discriminant(&a) == mem::discriminant(&b)
+}
+
+// the parts of `l` picked by `d`, written as in a square name
+fn partial_square(l: Location, (file, rank, layer): Disambiguation) -> String {
+    let name = l.square_name().unwrap_or_default();
+    name.chars()
+        .zip([file, rank, layer].iter())
+        .filter(|(_, &keep)| keep)
+        .map(|(c, _)| c)
+        .collect()
+}
+
+impl Board {
+    /// Writes `m`, which must be legal in this position, in algebraic notation.
+    pub fn format_move(&self, m: &Move, notation: Notation) -> String {
+        let mut text = if let Some(castles) = self.castling_notation(m) {
+            castles.to_owned()
+        } else {
+            let mut text = String::new();
+            if let Some(p) = piece_letter(m.piece().1) {
+                text.push(p.character());
+            }
+            match notation {
+                Notation::Long => text.push_str(&m.from().square_name().unwrap_or_default()),
+                Notation::Short => text.push_str(&partial_square(m.from(), self.disambiguation(m))),
+            }
+            if self.is_capture(m) {
+                text.push('x');
+            } else if notation == Notation::Long {
+                text.push('-');
+            }
+            text.push_str(&m.to().square_name().unwrap_or_default());
+            if let Some(p) = m.promotion() {
+                text.push('=');
+                text.push(p.character());
+            }
+            text
+        };
+
+        let mut after = *self;
+        after.make_move(m);
+        if after.is_in_check(after.side_to_move()) {
+            match after.outcome() {
+                Some(Outcome::Checkmate { .. }) => text.push('#'),
+                _ => text.push('+'),
+            }
+        }
+        text
+    }
+
+    /// Reads a move in either the long or the short form and finds the legal
+    /// move it names. Check marks and trailing `!` and `?` annotations are
+    /// ignored, as is whether a capture was marked with `x`. Castling may also
+    /// be written as the king's move.
+    pub fn parse_move(&self, text: &str) -> Result<Move, NotationError> {
+        let malformed = || NotationError::Malformed(text.to_owned());
+        let trimmed = text.trim().trim_end_matches(|c| "+#!?".contains(c));
+        let legal = self.legal_moves(self.side_to_move);
+
+        let castling = match trimmed {
+            "O-O" | "0-0" => Some("O-O"),
+            "O-O-O" | "0-0-0" => Some("O-O-O"),
+            _ => None,
+        };
+        let matches: Vec<Move> = if let Some(castles) = castling {
+            legal.into_iter()
+                .filter(|m| self.castling_notation(m) == Some(castles))
+                .collect()
+        } else {
+            let mut rest = trimmed;
+            let piece = match rest.chars().next() {
+                Some(c) if "KQRBN".contains(c) => {
+                    rest = &rest[1..];
+                    Some(c)
+                },
+                _ => None,
+            };
+
+            let mut promotion = None;
+            if let Some(i) = rest.find('=') {
+                let mut p = rest[i + 1..].chars();
+                promotion = match (p.next(), p.next()) {
+                    (Some(c), None) if "QRBN".contains(c) => Some(c),
+                    _ => return Err(malformed()),
+                };
+                rest = &rest[..i];
+            }
+
+            // the destination is always the last full square
+            if rest.len() < 3 || !rest.is_char_boundary(rest.len() - 3) {
+                return Err(malformed());
+            }
+            let to = Location::from_square_name(&rest[rest.len() - 3..]).ok_or_else(malformed)?;
+            rest = rest[..rest.len() - 3].trim_end_matches(['-', 'x']);
+
+            // whatever is left is some of the starting square, in order
+            let mut from: (Option<isize>, Option<isize>, Option<isize>) = (None, None, None);
+            let mut chars = rest.chars().peekable();
+            if let Some(&c) = chars.peek().filter(|c| ('a'..='h').contains(c)) {
+                from.0 = Some(c as isize - 'a' as isize);
+                chars.next();
+            }
+            if let Some(&c) = chars.peek().filter(|c| ('1'..='8').contains(c)) {
+                from.1 = Some(c as isize - '1' as isize);
+                chars.next();
+            }
+            if let Some(&c) = chars.peek().filter(|c| ('A'..='H').contains(c)) {
+                from.2 = Some(c as isize - 'A' as isize);
+                chars.next();
+            }
+            if chars.next().is_some() {
+                return Err(malformed());
+            }
+
+            legal.into_iter()
+                .filter(|m| {
+                    let p = m.piece().1;
+                    match piece {
+                        Some(c) => piece_letter(p).map(|p| p.character()) == Some(c),
+                        None => piece_letter(p).is_none(),
+                    }
+                })
+                .filter(|m| m.to() == to)
+                .filter(|m| {
+                    from.0.is_none_or(|x| m.from().x == x)
+                        && from.1.is_none_or(|y| m.from().y == y)
+                        && from.2.is_none_or(|z| m.from().z == z)
+                })
+                .filter(|m| m.promotion().map(|p| p.character()) == promotion)
+                .collect()
+        };
+
+        match matches.len() {
+            0 => Err(NotationError::NoSuchMove(text.to_owned())),
+            1 => Ok(matches[0]),
+            _ => Err(NotationError::Ambiguous(text.to_owned())),
+        }
+    }
+
+    // `O-O` or `O-O-O` for a castling move, by the direction the king moves
+    fn castling_notation(&self, m: &Move) -> Option<&'static str> {
+        self.castling_rook(m).map(|_| if m.to().x > m.from().x { "O-O" } else { "O-O-O" })
+    }
+
+    fn is_capture(&self, m: &Move) -> bool {
+        matches!(self.at(self.captured_square(m)), BoardState::Piece(_))
+    }
+
+    // the parts of the starting square the short form needs to tell `m` apart
+    // from other legal moves of the same kind of piece to the same square
+    fn disambiguation(&self, m: &Move) -> Disambiguation {
+        let is_pawn = piece_letter(m.piece().1).is_none();
+        let others: Vec<Location> = self.legal_moves(m.piece().0)
+            .into_iter()
+            .filter(|o| o.to() == m.to() && o.from() != m.from() && same_kind(o.piece().1, m.piece().1))
+            .map(|o| o.from())
+            .collect();
+        if others.is_empty() && !(is_pawn && self.is_capture(m)) {
+            return (false, false, false);
+        }
+        let from = m.from();
+        DISAMBIGUATIONS.iter()
+            // pawns keep their file so the move doesn't look like a piece's
+            .filter(|&&(file, _, _)| file || !is_pawn)
+            .find(|&&(file, rank, layer)| {
+                others.iter().all(|o| {
+                    (file && o.x != from.x) || (rank && o.y != from.y) || (layer && o.z != from.z)
+                })
+            })
+            .copied()
+            .unwrap_or((true, true, true))
+    }
+}
@@ -0,0 +1,42 @@
+use crate::{ Board, Move };
+
+/// Counts the leaf nodes of the legal move tree `depth` plies deep.
+pub fn perft(board: &Board, depth: u32) -> u64 {
+    let mut scratch = *board;
+    perft_inner(&mut scratch, depth)
+}
+
+/// The perft count below each legal move of the side to move, in move
+/// generation order. The counts sum to `perft(board, depth)`.
+pub fn perft_divide(board: &Board, depth: u32) -> Vec<(Move, u64)> {
+    if depth == 0 {
+        return Vec::new();
+    }
+    let mut scratch = *board;
+    board.legal_moves(board.side_to_move())
+        .into_iter()
+        .map(|m| {
+            let undo = scratch.make_move(&m);
+            let nodes = perft_inner(&mut scratch, depth - 1);
+            scratch.unmake_move(&undo);
+            (m, nodes)
+        })
+        .collect()
+}
+
+fn perft_inner(board: &mut Board, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    let moves = board.legal_moves(board.side_to_move());
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+    let mut nodes = 0;
+    for m in moves {
+        let undo = board.make_move(&m);
+        nodes += perft_inner(board, depth - 1);
+        board.unmake_move(&undo);
+    }
+    nodes
+}
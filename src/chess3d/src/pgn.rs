@@ -0,0 +1,531 @@
+//! A game record format modelled on PGN. A record is a list of tag pairs like
+//! `[White "Nolan"]` followed by the moves in short algebraic notation with
+//! move numbers, and ends with the result: `1-0`, `0-1`, `1/2-1/2` or `*`.
+//!
+//! ```text
+//! [Event "Casual game"]
+//! [Result "*"]
+//! [Rules "classic"]
+//!
+//! 1. e4A {a quiet start} e5H 2. Nc3A $1 (2. Nb3B Nc6H) Nf6H *
+//! ```
+//!
+//! The moves may carry `{comments}`, `; comments` running to the end of the
+//! line, numeric annotations like `$1` (or `!`, `?`, `!!`, `??`, `!?` and
+//! `?!` after the move) and `(variations)`, which replace the move before them.
+//!
+//! Games start from the standard position unless a `FEN` tag gives another,
+//! and the `Rules` tag names a rule preset or lists the pieces with triagonal
+//! moves as in the FEN rules field.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::fen::{ format_rules, parse_rules };
+use crate::{ Board, Colors, FenError, Game, GameResult, Move, MoveError, Notation, NotationError, RuleSet };
+
+/// A move in a record along with its annotations.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordMove {
+    pub r#move: Move,
+    // numeric annotation glyphs, like 1 for a good move
+    pub nags: Vec<u8>,
+    // a comment written before the move, only kept for the first move of a line
+    pub comment_before: Option<String>,
+    pub comment: Option<String>,
+    // alternatives to this move, each played from the position before it
+    pub variations: Vec<Vec<RecordMove>>,
+}
+
+impl RecordMove {
+    pub fn new(m: Move) -> RecordMove {
+        RecordMove {
+            r#move: m,
+            nags: Vec::new(),
+            comment_before: None,
+            comment: None,
+            variations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    // tag pairs in the order they are written
+    pub tags: Vec<(String, String)>,
+    // the main line
+    pub moves: Vec<RecordMove>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PgnError {
+    // lines are counted from 1
+    MalformedTag { line: usize },
+    UnterminatedComment { line: usize },
+    UnexpectedToken { line: usize, token: String },
+    // a `(` or `)` without its partner
+    UnbalancedVariation { line: usize },
+    IllegalMove { line: usize, text: String, error: NotationError },
+    InvalidFen(FenError),
+    InvalidRules(String),
+    // a move the game refused when the record was played out, by ply from 0
+    Rejected { ply: usize, error: MoveError },
+    NoGame,
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgnError::MalformedTag { line } => write!(f, "line {}: malformed tag pair", line),
+            PgnError::UnterminatedComment { line } => write!(f, "line {}: comment is never closed", line),
+            PgnError::UnexpectedToken { line, token } => write!(f, "line {}: unexpected '{}'", line, token),
+            PgnError::UnbalancedVariation { line } => write!(f, "line {}: unbalanced parentheses", line),
+            PgnError::IllegalMove { line, error, .. } => write!(f, "line {}: {}", line, error),
+            PgnError::InvalidFen(e) => write!(f, "invalid FEN tag: {}", e),
+            PgnError::InvalidRules(s) => write!(f, "invalid Rules tag '{}'", s),
+            PgnError::Rejected { ply, error } => write!(f, "move {} was rejected: {}", ply + 1, error),
+            PgnError::NoGame => write!(f, "no game found"),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+/// The result as written in records: `1-0`, `0-1`, `1/2-1/2`, or `*` while
+/// the game is still going.
+pub fn result_text(result: Option<GameResult>) -> &'static str {
+    match result {
+        Some(GameResult::Checkmate { winner: Colors::White }) => "1-0",
+        Some(GameResult::Checkmate { winner: Colors::Black }) => "0-1",
+        Some(_) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+
+// the suffixes that stand for the first six annotation glyphs
+const SUFFIX_NAGS: [(&str, u8); 6] = [("!!", 3), ("??", 4), ("!?", 5), ("?!", 6), ("!", 1), ("?", 2)];
+
+#[derive(Debug)]
+enum Token {
+    Tag(String, String),
+    MoveNumber,
+    Move(String, Option<u8>),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    Termination(String),
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match self {
+            Token::Tag(name, _) => format!("[{}", name),
+            Token::MoveNumber => "move number".to_owned(),
+            Token::Move(text, _) => text.clone(),
+            Token::Comment(_) => "{".to_owned(),
+            Token::Nag(n) => format!("${}", n),
+            Token::Open => "(".to_owned(),
+            Token::Close => ")".to_owned(),
+            Token::Termination(text) => text.clone(),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    // tokens already read, like the move after a move number with no space
+    pending: VecDeque<Token>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Lexer<'a> {
+        Lexer {
+            chars: text.chars().peekable(),
+            line: 1,
+            pending: VecDeque::new(),
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    // reads characters up to and including `end`, or None if the text runs out
+    fn read_until(&mut self, end: char) -> Option<String> {
+        let mut text = String::new();
+        loop {
+            match self.next_char()? {
+                c if c == end => return Some(text),
+                c => text.push(c),
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.next_char();
+        }
+    }
+
+    fn tag(&mut self) -> Result<Token, PgnError> {
+        let error = PgnError::MalformedTag { line: self.line };
+        self.next_char();
+        self.skip_whitespace();
+        let mut name = String::new();
+        while let Some(&c) = self.chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+            name.push(c);
+            self.next_char();
+        }
+        self.skip_whitespace();
+        if name.is_empty() || self.next_char() != Some('"') {
+            return Err(error);
+        }
+        let mut value = String::new();
+        loop {
+            match self.next_char() {
+                Some('"') => break,
+                Some('\\') => value.push(self.next_char().ok_or_else(|| error.clone())?),
+                Some('\n') | None => return Err(error),
+                Some(c) => value.push(c),
+            }
+        }
+        self.skip_whitespace();
+        if self.next_char() != Some(']') {
+            return Err(error);
+        }
+        Ok(Token::Tag(name, value))
+    }
+
+    // a move, a move number, or a move number with a move stuck to it
+    fn word(&mut self) -> Token {
+        let mut word = String::new();
+        while let Some(&c) = self.chars.peek().filter(|c| !c.is_whitespace() && !"(){}[];$".contains(**c)) {
+            word.push(c);
+            self.next_char();
+        }
+        if RESULTS.contains(&word.as_str()) {
+            return Token::Termination(word);
+        }
+        let digits = word.find(|c: char| !c.is_ascii_digit()).unwrap_or(word.len());
+        if digits > 0 && word[digits..].starts_with('.') {
+            let rest = word[digits..].trim_start_matches('.');
+            if !rest.is_empty() {
+                let token = Lexer::move_token(rest);
+                self.pending.push_back(token);
+            }
+            return Token::MoveNumber;
+        }
+        Lexer::move_token(&word)
+    }
+
+    fn move_token(word: &str) -> Token {
+        for &(suffix, nag) in SUFFIX_NAGS.iter() {
+            if let Some(text) = word.strip_suffix(suffix) {
+                if !text.ends_with(['!', '?']) {
+                    return Token::Move(text.to_owned(), Some(nag));
+                }
+            }
+        }
+        Token::Move(word.to_owned(), None)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, PgnError> {
+        if let Some(token) = self.pending.pop_front() {
+            return Ok(Some(token));
+        }
+        self.skip_whitespace();
+        let line = self.line;
+        let token = match self.chars.peek() {
+            None => return Ok(None),
+            Some('[') => self.tag()?,
+            Some('{') => {
+                self.next_char();
+                let comment = self.read_until('}').ok_or(PgnError::UnterminatedComment { line })?;
+                Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" "))
+            },
+            Some(';') => {
+                self.next_char();
+                let comment = self.read_until('\n').unwrap_or_default();
+                Token::Comment(comment.trim().to_owned())
+            },
+            Some('(') => {
+                self.next_char();
+                Token::Open
+            },
+            Some(')') => {
+                self.next_char();
+                Token::Close
+            },
+            Some('$') => {
+                self.next_char();
+                let mut digits = String::new();
+                while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                    self.next_char();
+                }
+                let nag = digits.parse().map_err(|_| PgnError::UnexpectedToken { line, token: format!("${}", digits) })?;
+                Token::Nag(nag)
+            },
+            Some('}') | Some(']') => {
+                let c = self.next_char().unwrap_or_default();
+                return Err(PgnError::UnexpectedToken { line, token: c.to_string() });
+            },
+            Some(_) => self.word(),
+        };
+        Ok(Some(token))
+    }
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    peeked: Option<Token>,
+}
+
+impl<'a> Parser<'a> {
+    fn next(&mut self) -> Result<Option<Token>, PgnError> {
+        match self.peeked.take() {
+            Some(token) => Ok(Some(token)),
+            None => self.lexer.next_token(),
+        }
+    }
+
+    fn game(&mut self) -> Result<Option<GameRecord>, PgnError> {
+        let mut record = GameRecord { tags: Vec::new(), moves: Vec::new() };
+        let first = loop {
+            match self.next()? {
+                Some(Token::Tag(name, value)) => record.tags.push((name, value)),
+                token => break token,
+            }
+        };
+        if first.is_none() && record.tags.is_empty() {
+            return Ok(None);
+        }
+        self.peeked = first;
+        let (moves, termination) = self.line(record.start_board()?, false)?;
+        record.moves = moves;
+        if let Some(result) = termination {
+            if record.tag("Result").is_none() {
+                record.set_tag("Result", &result);
+            }
+        }
+        Ok(Some(record))
+    }
+
+    // reads moves played from `board` up to the end of the game, or the end
+    // of the variation if `nested`; returns the termination marker if any
+    fn line(&mut self, mut board: Board, nested: bool) -> Result<(Vec<RecordMove>, Option<String>), PgnError> {
+        let mut moves: Vec<RecordMove> = Vec::new();
+        // the position before the last move, where its variations start
+        let mut before = board;
+        let mut comment_before: Option<String> = None;
+        loop {
+            let line = self.lexer.line;
+            let unexpected = |token: &Token| PgnError::UnexpectedToken { line, token: token.text() };
+            match self.next()? {
+                None if nested => return Err(PgnError::UnbalancedVariation { line }),
+                None => return Ok((moves, None)),
+                Some(Token::Close) if nested => return Ok((moves, None)),
+                Some(Token::Close) => return Err(PgnError::UnbalancedVariation { line }),
+                Some(Token::Termination(result)) if !nested => return Ok((moves, Some(result))),
+                // the next game's tags, when this one had no termination marker
+                Some(token @ Token::Tag(..)) if !nested => {
+                    self.peeked = Some(token);
+                    return Ok((moves, None));
+                },
+                Some(Token::MoveNumber) => {},
+                Some(Token::Move(text, nag)) => {
+                    let m = board.parse_move(&text)
+                        .map_err(|error| PgnError::IllegalMove { line, text: text.clone(), error })?;
+                    before = board;
+                    board.make_move(&m);
+                    let mut record_move = RecordMove::new(m);
+                    record_move.nags.extend(nag);
+                    record_move.comment_before = comment_before.take();
+                    moves.push(record_move);
+                },
+                Some(Token::Comment(comment)) => {
+                    let target = match moves.last_mut() {
+                        Some(m) => &mut m.comment,
+                        None => &mut comment_before,
+                    };
+                    *target = Some(match target.take() {
+                        Some(c) => format!("{} {}", c, comment),
+                        None => comment,
+                    });
+                },
+                Some(token @ Token::Nag(_)) => match (moves.last_mut(), token) {
+                    (Some(m), Token::Nag(n)) => m.nags.push(n),
+                    (_, token) => return Err(unexpected(&token)),
+                },
+                Some(Token::Open) => {
+                    if moves.is_empty() {
+                        return Err(unexpected(&Token::Open));
+                    }
+                    let (variation, _) = self.line(before, true)?;
+                    if let Some(m) = moves.last_mut() {
+                        m.variations.push(variation);
+                    }
+                },
+                Some(token) => return Err(unexpected(&token)),
+            }
+        }
+    }
+}
+
+// a comment in braces; a `}` inside would end it early, so it becomes `)`
+fn comment_word(comment: &str) -> String {
+    format!("{{{}}}", comment.replace('}', ")"))
+}
+
+// writes the moves of a line played from `board` as separate words
+fn line_words(moves: &[RecordMove], mut board: Board, words: &mut Vec<String>) {
+    let mut needs_number = true;
+    for m in moves {
+        if let Some(comment) = &m.comment_before {
+            words.push(comment_word(comment));
+        }
+        match board.side_to_move() {
+            Colors::White => words.push(format!("{}.", board.fullmove_number())),
+            Colors::Black if needs_number => words.push(format!("{}...", board.fullmove_number())),
+            Colors::Black => {},
+        }
+        words.push(board.format_move(&m.r#move, Notation::Short));
+        words.extend(m.nags.iter().map(|n| format!("${}", n)));
+        needs_number = false;
+        if let Some(comment) = &m.comment {
+            words.push(comment_word(comment));
+            needs_number = true;
+        }
+        for variation in &m.variations {
+            let mut inner = Vec::new();
+            line_words(variation, board, &mut inner);
+            if let Some(first) = inner.first_mut() {
+                first.insert(0, '(');
+            }
+            if let Some(last) = inner.last_mut() {
+                last.push(')');
+            }
+            words.append(&mut inner);
+            needs_number = true;
+        }
+        board.make_move(&m.r#move);
+    }
+}
+
+impl GameRecord {
+    /// A record of the moves of `game`, with the standard tags filled in as
+    /// far as the game knows them.
+    pub fn from_game(game: &Game) -> GameRecord {
+        let start = game.start_board();
+        let mut record = GameRecord {
+            tags: vec![
+                ("Event".to_owned(), "?".to_owned()),
+                ("Site".to_owned(), "?".to_owned()),
+                ("Date".to_owned(), "????.??.??".to_owned()),
+                ("Round".to_owned(), "?".to_owned()),
+                ("White".to_owned(), "?".to_owned()),
+                ("Black".to_owned(), "?".to_owned()),
+                ("Result".to_owned(), result_text(game.result()).to_owned()),
+            ],
+            moves: game.moves().iter().map(|&m| RecordMove::new(m)).collect(),
+        };
+        let rules = start.rules();
+        record.set_tag("Rules", &rules.name().map_or_else(|| format_rules(rules), str::to_owned));
+        if start.to_fen() != Board::with_rules(rules).to_fen() {
+            record.set_tag("SetUp", "1");
+            record.set_tag("FEN", &start.to_fen());
+        }
+        record
+    }
+
+    /// Reads every game in `text`.
+    pub fn parse_all(text: &str) -> Result<Vec<GameRecord>, PgnError> {
+        let mut parser = Parser { lexer: Lexer::new(text), peeked: None };
+        let mut records = Vec::new();
+        while let Some(record) = parser.game()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Reads the first game in `text`.
+    pub fn parse(text: &str) -> Result<GameRecord, PgnError> {
+        let mut parser = Parser { lexer: Lexer::new(text), peeked: None };
+        parser.game()?.ok_or(PgnError::NoGame)
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Sets a tag, replacing its value if it is already present.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value.to_owned(),
+            None => self.tags.push((name.to_owned(), value.to_owned())),
+        }
+    }
+
+    /// The position the game starts from, from the `FEN` and `Rules` tags.
+    pub fn start_board(&self) -> Result<Board, PgnError> {
+        let mut board = match self.tag("FEN") {
+            Some(fen) => Board::from_fen(fen).map_err(PgnError::InvalidFen)?,
+            None => Board::new(),
+        };
+        if let Some(rules) = self.tag("Rules") {
+            let parsed = RuleSet::preset(rules).or_else(|| parse_rules(rules).ok());
+            board.set_rules(parsed.ok_or_else(|| PgnError::InvalidRules(rules.to_owned()))?);
+        }
+        Ok(board)
+    }
+
+    /// Plays the main line into a `Game`.
+    pub fn to_game(&self) -> Result<Game, PgnError> {
+        let mut game = Game::from_board(self.start_board()?);
+        for (ply, m) in self.moves.iter().enumerate() {
+            game.play(&m.r#move).map_err(|error| PgnError::Rejected { ply, error })?;
+        }
+        Ok(game)
+    }
+
+    /// Writes the record out, wrapping the move text at 80 columns. Fails if
+    /// the `FEN` or `Rules` tag is invalid, since the moves are written from
+    /// the position they give.
+    pub fn to_pgn(&self) -> Result<String, PgnError> {
+        let mut words = Vec::new();
+        line_words(&self.moves, self.start_board()?, &mut words);
+        words.push(self.tag("Result").unwrap_or("*").to_owned());
+
+        let mut text = String::new();
+        for (name, value) in &self.tags {
+            text += &format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\""));
+        }
+        text.push('\n');
+        let mut width = 0;
+        for word in words {
+            if width > 0 && width + 1 + word.len() > 80 {
+                text.push('\n');
+                width = 0;
+            } else if width > 0 {
+                text.push(' ');
+                width += 1;
+            }
+            text += &word;
+            width += word.len();
+        }
+        text.push('\n');
+        Ok(text)
+    }
+}
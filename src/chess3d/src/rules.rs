@@ -0,0 +1,51 @@
+use serde::{ Serialize, Deserialize };
+
+/// Optional movement rules. Triagonal moves run through the corners of a
+/// cell, changing all three coordinates at once, like (1, 1, 1).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub triagonal_bishops: bool,
+    pub triagonal_queens: bool,
+    pub triagonal_kings: bool,
+}
+
+impl RuleSet {
+    /// Sliders and kings stay within the planes of the cube.
+    pub const CLASSIC: RuleSet = RuleSet {
+        triagonal_bishops: false,
+        triagonal_queens: false,
+        triagonal_kings: false,
+    };
+
+    /// Raumschach-style movement: bishops, queens and kings also move along
+    /// the space diagonals.
+    pub const RAUMSCHACH: RuleSet = RuleSet {
+        triagonal_bishops: true,
+        triagonal_queens: true,
+        triagonal_kings: true,
+    };
+
+    pub const PRESETS: [(&'static str, RuleSet); 2] = [
+        ("classic", RuleSet::CLASSIC),
+        ("raumschach", RuleSet::RAUMSCHACH),
+    ];
+
+    pub fn preset(name: &str) -> Option<RuleSet> {
+        RuleSet::PRESETS.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, r)| r)
+    }
+
+    /// The name of the preset these rules match, if any.
+    pub fn name(&self) -> Option<&'static str> {
+        RuleSet::PRESETS.iter()
+            .find(|(_, r)| r == self)
+            .map(|&(n, _)| n)
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> RuleSet {
+        RuleSet::CLASSIC
+    }
+}
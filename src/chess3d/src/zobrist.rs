@@ -0,0 +1,66 @@
+//! Zobrist keys for `Board::hash`. The keys are generated at compile time from
+//! a fixed seed, so a position hashes the same in every build and on every machine.
+
+use crate::{ BoardState, Colors, Location, Pieces };
+use crate::bitboard::square_index;
+
+const SQUARES: usize = 512;
+// first-move flags count as separate kinds so positions that differ only in
+// castling rights or pawn double steps hash differently
+const PIECE_KINDS: usize = 9;
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+const fn generate_keys<const N: usize>(seed: u64) -> [u64; N] {
+    let mut keys = [0; N];
+    let mut state = seed;
+    let mut i = 0;
+    while i < N {
+        let (next, key) = splitmix64(state);
+        state = next;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+}
+
+static PIECE_KEYS: [u64; SQUARES * PIECE_KINDS * 2] = generate_keys(0x3D_C4E5_5000_0001);
+static EN_PASSANT_KEYS: [u64; SQUARES] = generate_keys(0x3D_C4E5_5000_0002);
+pub(crate) const BLACK_TO_MOVE: u64 = 0x5A5A_17C3_D00D_F00D;
+
+fn piece_index(color: Colors, piece: Pieces) -> usize {
+    let kind = match piece {
+        Pieces::Pawn(false) => 0,
+        Pieces::Pawn(true) => 1,
+        Pieces::Rook(false) => 2,
+        Pieces::Rook(true) => 3,
+        Pieces::Bishop => 4,
+        Pieces::Queen => 5,
+        Pieces::King(false) => 6,
+        Pieces::King(true) => 7,
+        Pieces::Knight => 8,
+    };
+    match color {
+        Colors::White => kind,
+        Colors::Black => kind + PIECE_KINDS,
+    }
+}
+
+pub(crate) fn square_key(l: Location, s: BoardState) -> u64 {
+    match s {
+        BoardState::Empty => 0,
+        BoardState::Piece((color, piece)) => {
+            PIECE_KEYS[square_index(l) * PIECE_KINDS * 2 + piece_index(color, piece)]
+        },
+    }
+}
+
+pub(crate) fn en_passant_key(target: Location) -> u64 {
+    EN_PASSANT_KEYS[square_index(target)]
+}
@@ -0,0 +1,87 @@
+//! Positions and helpers shared by the tests. Each test file uses only some of
+//! them.
+
+#![allow(dead_code)]
+
+use chess3d::{ Board, BoardState, Colors, Dimensions, Location, Move, Pieces, RuleSet };
+
+use BoardState::*;
+use Colors::*;
+use Pieces::*;
+
+pub type Square = (isize, isize, isize);
+
+pub fn empty_board() -> Board {
+    Board::empty(Dimensions::STANDARD, RuleSet::default())
+}
+
+pub fn place(b: &mut Board, pieces: &[(Square, Colors, Pieces)]) {
+    for &(l, c, p) in pieces {
+        b.set(l.into(), Piece((c, p)));
+    }
+}
+
+// the legal move between two squares, panicking if there isn't one
+pub fn legal_move(b: &Board, from: Square, to: Square) -> Move {
+    b.legal_moves(b.side_to_move())
+        .into_iter()
+        .find(|m| m.from() == from.into() && m.to() == to.into())
+        .expect("move should be legal")
+}
+
+// the starting position with the back ranks cleared between kings and rooks
+pub fn castling_position() -> Board {
+    let mut b = Board::new();
+    for &x in [1, 2, 3, 5, 6].iter() {
+        b.set(Location::new(x, 0, 0), Empty);
+        b.set(Location::new(x, 7, 7), Empty);
+    }
+    b
+}
+
+// a white pawn double-steps next to a black pawn, which may take it en passant
+pub fn en_passant_position() -> Board {
+    let mut b = empty_board();
+    place(&mut b, &[
+        ((4, 0, 3), White, King(true)),
+        ((4, 7, 4), Black, King(true)),
+        ((2, 1, 0), White, Pawn(false)),
+        ((3, 3, 0), Black, Pawn(true)),
+        ((5, 1, 2), White, Pawn(false)),
+        ((6, 1, 4), Black, Pawn(true)),
+    ]);
+    b
+}
+
+// pawns one step from the far edge, with pieces to capture on promotion
+pub fn promotion_position() -> Board {
+    let mut b = empty_board();
+    place(&mut b, &[
+        ((0, 0, 3), White, King(true)),
+        ((7, 7, 4), Black, King(true)),
+        ((1, 7, 6), White, Pawn(true)),
+        ((2, 6, 7), White, Pawn(true)),
+        ((0, 7, 7), Black, Knight),
+        ((3, 7, 7), Black, Rook(true)),
+        ((5, 0, 1), Black, Pawn(true)),
+        ((4, 0, 0), White, Bishop),
+    ]);
+    b
+}
+
+// a xorshift generator, so the tests need no dependencies
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    // one of `items`, which must not be empty
+    pub fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        items[(self.0 % items.len() as u64) as usize]
+    }
+}
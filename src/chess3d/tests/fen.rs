@@ -0,0 +1,165 @@
+//! Reading and writing positions in the FEN-style notation, and the error
+//! given for each way a position can be written wrongly.
+
+use chess3d::{ Board, Colors, Dimensions, FenError, Location, Move, Pieces, RuleSet };
+
+const START: &str = "8/8/8/8/8/8/PPPPPPPP/RNBQKBNR|8/8/8/8/8/8/8/8|8/8/8/8/8/8/8/8|8/8/8/8/8/8/8/8\
+|8/8/8/8/8/8/8/8|8/8/8/8/8/8/8/8|8/8/8/8/8/8/8/8|rnbqkbnr/pppppppp/8/8/8/8/8/8 w - 0 1 -";
+
+fn round_trip(fen: &str) -> Board {
+    let board = Board::from_fen(fen).unwrap();
+    assert_eq!(board.to_fen(), fen);
+    assert_eq!(board.hash(), board.compute_hash());
+    board
+}
+
+fn error(fen: &str) -> FenError {
+    match Board::from_fen(fen) {
+        Ok(_) => panic!("{} should not parse", fen),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn start_position() {
+    assert_eq!(Board::new().to_fen(), START);
+    let board = round_trip(START);
+    assert_eq!(board.hash(), Board::new().hash());
+    assert_eq!(board.dimensions(), Dimensions::STANDARD);
+    assert_eq!(board.side_to_move(), Colors::White);
+}
+
+#[test]
+fn en_passant() {
+    let mut board = Board::new();
+    let m = Move::new(Location::new(4, 1, 0), Location::new(4, 3, 0), (Colors::White, Pieces::Pawn(false)));
+    board.try_move(&m).unwrap();
+    let fen = board.to_fen();
+    assert!(fen.contains(" b e3A:e4A 0 1 "), "{}", fen);
+
+    let read = round_trip(&fen);
+    let ep = read.en_passant().unwrap();
+    assert_eq!((ep.target, ep.pawn), (Location::new(4, 2, 0), Location::new(4, 3, 0)));
+    assert_eq!(read.hash(), board.hash());
+}
+
+#[test]
+fn moved_flags() {
+    let board = round_trip("r3k*2r*/8/8/8/8/8/P*7/R3K2R b - 3 12 -");
+    assert_eq!(board.halfmove_clock(), 3);
+    assert_eq!(board.fullmove_number(), 12);
+    // the moved king and rook can't castle, so the flags change the hash
+    let unmoved = Board::from_fen("r3k2r/8/8/8/8/8/P*7/R3K2R b - 3 12 -").unwrap();
+    assert_ne!(board.hash(), unmoved.hash());
+}
+
+#[test]
+fn rules_field() {
+    assert_eq!(round_trip("4k3/8/8/8/8/8/8/4K3 w - 0 1 BQK").rules(), RuleSet::RAUMSCHACH);
+    assert!(round_trip("4k3/8/8/8/8/8/8/4K3 w - 0 1 B").rules().triagonal_bishops);
+    // the field may be left out
+    let board = Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - 0 1").unwrap();
+    assert_eq!(board.rules(), RuleSet::CLASSIC);
+    assert_eq!(board.to_fen(), "4k3/8/8/8/8/8/8/4K3 w - 0 1 -");
+}
+
+#[test]
+fn small_boards() {
+    for name in &["raumschach", "training", "flat"] {
+        let board = Board::preset(name).unwrap();
+        assert_eq!(round_trip(&board.to_fen()).dimensions(), board.dimensions());
+    }
+}
+
+#[test]
+fn missing_field() {
+    assert_eq!(error(""), FenError::MissingField("placement"));
+    assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - 0"), FenError::MissingField("fullmove number"));
+}
+
+#[test]
+fn too_many_fields() {
+    assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - 0 1 - extra"), FenError::TooManyFields);
+}
+
+#[test]
+fn invalid_character() {
+    assert_eq!(
+        error("4k3/8/8/8/8/8/8/4K2X w - 0 1"),
+        FenError::InvalidCharacter { layer: 0, rank: 0, character: 'X' },
+    );
+}
+
+#[test]
+fn misplaced_moved_flag() {
+    assert_eq!(error("4k3/8/8/8/8/8/8/*4K3 w - 0 1"), FenError::MisplacedMovedFlag { layer: 0, rank: 0 });
+    assert_eq!(error("4k3/8/8/8/8/8/N*7/4K3 w - 0 1"), FenError::MisplacedMovedFlag { layer: 0, rank: 1 });
+}
+
+#[test]
+fn too_many_files() {
+    // the first rank is the odd one, and is the one blamed
+    let e = error("9/8 w - 0 1");
+    assert_eq!(e, FenError::TooManyFiles { layer: 0, rank: 1, files: 9 });
+    assert_eq!(e.to_string(), "rank 2 of layer A has 9 files, more than the 8 allowed");
+}
+
+#[test]
+fn rank_length() {
+    let e = error("4k3/8/8/8/8/8/7/4K3|8/8/8/8/8/8/8/8 w - 0 1");
+    assert_eq!(e, FenError::RankLength { layer: 0, rank: 1, files: 7, expected: 8 });
+    assert_eq!(e.to_string(), "rank 2 of layer A has 7 files, but the first rank has 8");
+    assert_eq!(
+        error("8/8|8/7 w - 0 1"),
+        FenError::RankLength { layer: 1, rank: 0, files: 7, expected: 8 },
+    );
+}
+
+#[test]
+fn rank_count() {
+    assert_eq!(error("8/8|8/8/8 w - 0 1"), FenError::RankCount { layer: 1, ranks: 3, expected: 2 });
+}
+
+#[test]
+fn invalid_dimensions() {
+    assert_eq!(error("8 w - 0 1"), FenError::InvalidDimensions { files: 8, ranks: 1, layers: 1 });
+    let nine_layers = ["8/8"; 9].join("|");
+    assert_eq!(
+        error(&format!("{} w - 0 1", nine_layers)),
+        FenError::InvalidDimensions { files: 8, ranks: 2, layers: 9 },
+    );
+}
+
+#[test]
+fn invalid_side_to_move() {
+    assert_eq!(error("4k3/8/8/8/8/8/8/4K3 x - 0 1"), FenError::InvalidSideToMove("x".to_owned()));
+}
+
+#[test]
+fn invalid_en_passant() {
+    for ep in &["e3A", "e3A:e4A:e5A", "z9A:e4A", "e3A:d4A", "e5A:e4A"] {
+        let fen = format!("4k3/8/8/8/4P*3/8/8/4K3 b {} 0 1", ep);
+        assert_eq!(error(&fen), FenError::InvalidEnPassant((*ep).to_owned()), "{}", ep);
+    }
+    assert!(Board::from_fen("4k3/8/8/8/4P*3/8/8/4K3 b e3A:e4A 0 1").is_ok());
+    // only the side that just moved can have double-stepped
+    assert!(matches!(error("4k3/8/8/8/4P*3/8/8/4K3 w e3A:e4A 0 1"), FenError::InvalidEnPassant(_)));
+}
+
+#[test]
+fn invalid_number() {
+    assert_eq!(
+        error("4k3/8/8/8/8/8/8/4K3 w - x 1"),
+        FenError::InvalidNumber { field: "halfmove clock", value: "x".to_owned() },
+    );
+    assert_eq!(
+        error("4k3/8/8/8/8/8/8/4K3 w - 0 0"),
+        FenError::InvalidNumber { field: "fullmove number", value: "0".to_owned() },
+    );
+}
+
+#[test]
+fn invalid_rules() {
+    assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - 0 1 BX"), FenError::InvalidRules("BX".to_owned()));
+    assert_eq!(error("4k3/8/8/8/8/8/8/4K3 w - 0 1 BB"), FenError::InvalidRules("BB".to_owned()));
+}
@@ -0,0 +1,151 @@
+//! How a `Game` ends: checkmate, threefold repetition, the fifty-move rule and
+//! insufficient material, and how `undo` reopens a finished game.
+
+mod common;
+
+use chess3d::{ Board, Colors, Game, GameResult, MoveError, Pieces };
+
+use common::{ empty_board, legal_move, place, Square };
+
+use Colors::*;
+use Pieces::*;
+
+// plays the legal move between two squares
+fn play(game: &mut Game, from: Square, to: Square) {
+    let m = legal_move(game.board(), from, to);
+    game.play(&m).unwrap();
+}
+
+// a game with the kings on their starting squares and `pieces`
+fn kings_and(pieces: &[(Square, Colors, Pieces)]) -> Game {
+    let mut b = empty_board();
+    place(&mut b, &[((4, 0, 0), White, King(true)), ((4, 7, 7), Black, King(true))]);
+    place(&mut b, pieces);
+    Game::from_board(b)
+}
+
+// the black king is shut into its corner by its own pawns, and the rook can
+// reach its rank
+fn mate_in_one() -> Game {
+    let mut b = empty_board();
+    place(&mut b, &[
+        ((4, 0, 0), White, King(true)),
+        ((7, 0, 7), White, Rook(true)),
+        ((0, 7, 7), Black, King(true)),
+        ((0, 6, 7), Black, Pawn(true)),
+        ((1, 6, 7), Black, Pawn(true)),
+        ((0, 7, 6), Black, Pawn(true)),
+        ((1, 7, 6), Black, Pawn(true)),
+        ((0, 6, 6), Black, Pawn(true)),
+        ((1, 6, 6), Black, Pawn(true)),
+    ]);
+    Game::from_board(b)
+}
+
+#[test]
+fn checkmate_ends_the_game() {
+    let mut g = mate_in_one();
+    assert_eq!(g.result(), None);
+    play(&mut g, (7, 0, 7), (7, 7, 7));
+    assert_eq!(g.result(), Some(GameResult::Checkmate { winner: White }));
+    assert_eq!(g.result().unwrap().to_string(), "Checkmate, White wins");
+    assert_eq!(g.result().and_then(|r| r.winner()), Some(White));
+    assert!(!g.board().is_running());
+
+    let m = g.board().legal_moves(White)[0];
+    assert!(matches!(g.play(&m), Err(MoveError::GameOver)));
+}
+
+#[test]
+fn undo_reopens_a_finished_game() {
+    let mut g = mate_in_one();
+    play(&mut g, (7, 0, 7), (7, 7, 7));
+    assert!(g.result().is_some());
+
+    let m = g.undo().unwrap();
+    assert_eq!((m.from(), m.to()), ((7, 0, 7).into(), (7, 7, 7).into()));
+    assert_eq!(g.result(), None);
+    assert!(g.board().is_running());
+    assert!(g.moves().is_empty());
+    play(&mut g, (4, 0, 0), (5, 0, 0));
+    assert_eq!(g.result(), None);
+
+    assert!(g.undo().is_some());
+    assert!(g.undo().is_none());
+}
+
+#[test]
+fn threefold_repetition() {
+    // the knights go out and back twice; the third time the starting
+    // position comes round the game is drawn
+    let mut g = Game::new();
+    for round in 0..2 {
+        assert_eq!(g.repetitions(), round + 1);
+        play(&mut g, (1, 0, 0), (2, 2, 0));
+        play(&mut g, (1, 7, 7), (2, 5, 7));
+        play(&mut g, (2, 2, 0), (1, 0, 0));
+        assert_eq!(g.result(), None);
+        play(&mut g, (2, 5, 7), (1, 7, 7));
+    }
+    assert_eq!(g.repetitions(), 3);
+    assert_eq!(g.result(), Some(GameResult::ThreefoldRepetition));
+
+    g.undo();
+    assert_eq!(g.result(), None);
+    assert_eq!(g.repetitions(), 2);
+}
+
+#[test]
+fn fifty_move_rule() {
+    // 98 plies of knights going out and back, which ends with them out
+    let mut b = Board::new();
+    let shuffle = [((1, 0, 0), (2, 2, 0)), ((1, 7, 7), (2, 5, 7)), ((2, 2, 0), (1, 0, 0)), ((2, 5, 7), (1, 7, 7))];
+    for &(from, to) in shuffle.iter().cycle().take(98) {
+        let m = legal_move(&b, from, to);
+        b.make_move(&m);
+    }
+    assert_eq!(b.halfmove_clock(), 98);
+
+    let mut g = Game::from_board(b);
+    play(&mut g, (2, 2, 0), (1, 0, 0));
+    assert_eq!(g.result(), None);
+    play(&mut g, (2, 5, 7), (1, 7, 7));
+    assert_eq!(g.board().halfmove_clock(), 100);
+    assert_eq!(g.result(), Some(GameResult::FiftyMoveRule));
+
+    // a capture or pawn move resets the clock
+    let mut g = Game::from_board(b);
+    play(&mut g, (2, 2, 0), (1, 0, 0));
+    play(&mut g, (4, 6, 7), (4, 5, 7));
+    assert_eq!(g.board().halfmove_clock(), 0);
+    assert_eq!(g.result(), None);
+}
+
+#[test]
+fn insufficient_material() {
+    let drawn: [&[(Square, Colors, Pieces)]; 4] = [
+        &[],
+        &[((5, 0, 0), White, Knight)],
+        &[((5, 0, 0), White, Bishop)],
+        // both bishops on squares of one colour
+        &[((2, 0, 0), White, Bishop), ((4, 2, 0), White, Bishop)],
+    ];
+    for pieces in drawn.iter() {
+        assert_eq!(kings_and(pieces).result(), Some(GameResult::InsufficientMaterial), "{:?}", pieces);
+    }
+    let playable: [&[(Square, Colors, Pieces)]; 4] = [
+        &[((7, 0, 0), White, Rook(true))],
+        &[((0, 1, 0), White, Pawn(false))],
+        &[((3, 0, 0), White, Knight), ((5, 0, 0), White, Knight)],
+        // bishops on both colours
+        &[((2, 0, 0), White, Bishop), ((5, 0, 0), White, Bishop)],
+    ];
+    for pieces in playable.iter() {
+        assert_eq!(kings_and(pieces).result(), None, "{:?}", pieces);
+    }
+
+    // taking the last piece that could mate draws the game
+    let mut g = kings_and(&[((4, 1, 0), Black, Rook(true))]);
+    play(&mut g, (4, 0, 0), (4, 1, 0));
+    assert_eq!(g.result(), Some(GameResult::InsufficientMaterial));
+}
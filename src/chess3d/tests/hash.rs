@@ -0,0 +1,81 @@
+//! The incrementally updated hash against one recomputed from scratch, through
+//! every kind of move and back again with `unmake_move`.
+
+mod common;
+
+use chess3d::{ Board, Move, Pieces };
+
+use common::{ castling_position, en_passant_position, promotion_position, Rng };
+
+fn is_castling(m: &Move) -> bool {
+    matches!(m.piece().1, Pieces::King(_)) && (m.to().x - m.from().x).abs() == 2
+}
+
+// plays every legal move to `depth`, checking the hash after each move, and
+// that unmaking it restores the position and a hash that matches it
+fn check_moves(b: &mut Board, depth: usize) {
+    if depth == 0 {
+        return;
+    }
+    for m in b.legal_moves(b.side_to_move()) {
+        let before = b.to_fen();
+        let undo = b.make_move(&m);
+        assert_eq!(b.hash(), b.compute_hash(), "after {}", b.to_fen());
+        check_moves(b, depth - 1);
+        b.unmake_move(&undo);
+        assert_eq!(b.to_fen(), before);
+        assert_eq!(b.hash(), b.compute_hash(), "after unmaking in {}", before);
+    }
+}
+
+#[test]
+fn start_position() {
+    let mut b = Board::new();
+    assert_eq!(b.hash(), b.compute_hash());
+    check_moves(&mut b, 2);
+}
+
+#[test]
+fn castling() {
+    let mut b = castling_position();
+    assert!(b.legal_moves(b.side_to_move()).iter().any(is_castling));
+    check_moves(&mut b, 2);
+}
+
+#[test]
+fn en_passant() {
+    // the target appears with the double step, is taken, and disappears
+    // after any other reply
+    let mut b = en_passant_position();
+    assert_eq!(b.hash(), b.compute_hash());
+    check_moves(&mut b, 3);
+}
+
+#[test]
+fn promotion() {
+    let mut b = promotion_position();
+    assert!(b.legal_moves(b.side_to_move()).iter().any(|m| m.promotion().is_some()));
+    check_moves(&mut b, 2);
+}
+
+#[test]
+fn long_playout() {
+    let mut rng = Rng::new(0x9e37_79b9_7f4a_7c15);
+    let mut b = Board::new();
+    let mut played = Vec::new();
+    for _ in 0..300 {
+        let moves = b.legal_moves(b.side_to_move());
+        if moves.is_empty() {
+            break;
+        }
+        let m = rng.pick(&moves);
+        played.push((b.to_fen(), b.make_move(&m)));
+        assert_eq!(b.hash(), b.compute_hash());
+    }
+    while let Some((fen, undo)) = played.pop() {
+        b.unmake_move(&undo);
+        assert_eq!(b.to_fen(), fen);
+        assert_eq!(b.hash(), b.compute_hash());
+    }
+    assert_eq!(b.hash(), Board::new().hash());
+}
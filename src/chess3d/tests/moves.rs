@@ -0,0 +1,120 @@
+//! Playing moves on a `Board`: the error `try_move` gives for each kind of
+//! move it refuses, and `unmake_move` restoring the position before a move.
+
+mod common;
+
+use chess3d::{ Board, Colors, Location, Move, MoveError, Pieces };
+
+use common::{ castling_position, empty_board, en_passant_position, place, promotion_position, Square };
+
+use Colors::*;
+use Pieces::*;
+
+fn mv(from: Square, to: Square, piece: (Colors, Pieces)) -> Move {
+    Move::new(from.into(), to.into(), piece)
+}
+
+// tries `m`, checking that the board is left as it was
+fn refused(b: &mut Board, m: &Move) -> MoveError {
+    let (fen, hash) = (b.to_fen(), b.hash());
+    let error = b.try_move(m).expect_err("move should be refused");
+    assert_eq!((b.to_fen(), b.hash()), (fen, hash), "after {:?}", error);
+    error
+}
+
+#[test]
+fn out_of_bounds() {
+    let mut b = Board::new();
+    assert_eq!(refused(&mut b, &mv((4, 1, 0), (4, 8, 0), (White, Pawn(false)))), MoveError::OutOfBounds);
+    assert_eq!(refused(&mut b, &mv((4, -1, 0), (4, 1, 0), (White, Pawn(false)))), MoveError::OutOfBounds);
+}
+
+#[test]
+fn no_piece_at_source() {
+    let mut b = Board::new();
+    assert_eq!(refused(&mut b, &mv((4, 3, 0), (4, 4, 0), (White, Pawn(false)))), MoveError::NoPieceAtSource);
+}
+
+#[test]
+fn not_your_turn() {
+    let mut b = Board::new();
+    assert_eq!(refused(&mut b, &mv((4, 6, 7), (4, 5, 7), (Black, Pawn(false)))), MoveError::NotYourTurn);
+}
+
+#[test]
+fn piece_mismatch() {
+    let mut b = Board::new();
+    // a pawn passed off as a queen, and an unmoved pawn as a moved one
+    assert_eq!(refused(&mut b, &mv((4, 1, 0), (4, 5, 0), (White, Queen))), MoveError::PieceMismatch);
+    assert_eq!(refused(&mut b, &mv((4, 1, 0), (4, 2, 0), (White, Pawn(true)))), MoveError::PieceMismatch);
+}
+
+#[test]
+fn illegal_destination() {
+    let mut b = Board::new();
+    assert_eq!(refused(&mut b, &mv((4, 1, 0), (4, 4, 0), (White, Pawn(false)))), MoveError::IllegalDestination);
+    assert_eq!(refused(&mut b, &mv((0, 0, 0), (0, 2, 0), (White, Rook(false)))), MoveError::IllegalDestination);
+}
+
+#[test]
+fn invalid_promotion() {
+    let mut b = promotion_position();
+    let pawn = (White, Pawn(true));
+    let promote = |piece| Move::new_promotion(Location::new(1, 7, 6), Location::new(1, 7, 7), pawn, piece);
+    // reaching the far edge without naming a piece, or naming a king
+    assert_eq!(refused(&mut b, &mv((1, 7, 6), (1, 7, 7), pawn)), MoveError::InvalidPromotion);
+    assert_eq!(refused(&mut b, &promote(King(true))), MoveError::InvalidPromotion);
+    assert!(b.try_move(&promote(Queen)).is_ok());
+
+    // naming a piece short of the far edge
+    let mut b = Board::new();
+    let early = Move::new_promotion(Location::new(4, 1, 0), Location::new(4, 2, 0), (White, Pawn(false)), Queen);
+    assert_eq!(refused(&mut b, &early), MoveError::InvalidPromotion);
+}
+
+#[test]
+fn leaves_king_in_check() {
+    // the knight is pinned to its king by the rook
+    let mut b = empty_board();
+    place(&mut b, &[
+        ((4, 0, 0), White, King(true)),
+        ((4, 2, 0), White, Knight),
+        ((4, 7, 0), Black, Rook(true)),
+        ((0, 7, 7), Black, King(true)),
+    ]);
+    assert_eq!(refused(&mut b, &mv((4, 2, 0), (5, 4, 0), (White, Knight))), MoveError::LeavesKingInCheck);
+
+    // and a king may not step into the rook's file
+    let mut b = empty_board();
+    place(&mut b, &[
+        ((3, 0, 0), White, King(true)),
+        ((4, 7, 0), Black, Rook(true)),
+        ((0, 7, 7), Black, King(true)),
+    ]);
+    assert_eq!(refused(&mut b, &mv((3, 0, 0), (4, 0, 0), (White, King(true)))), MoveError::LeavesKingInCheck);
+}
+
+// plays every legal move to `depth`, checking that unmaking each one restores
+// the position from before it
+fn check_unmake(b: &mut Board, depth: usize) {
+    if depth == 0 {
+        return;
+    }
+    for m in b.legal_moves(b.side_to_move()) {
+        let before = b.to_fen();
+        let undo = b.make_move(&m);
+        check_unmake(b, depth - 1);
+        b.unmake_move(&undo);
+        assert_eq!(b.to_fen(), before, "after unmaking {:?}", m);
+    }
+}
+
+#[test]
+fn unmake_restores_the_position() {
+    check_unmake(&mut Board::new(), 1);
+    check_unmake(&mut castling_position(), 1);
+    // the second ply takes en passant, and captures and promotes after a
+    // quiet move has started the halfmove clock
+    check_unmake(&mut en_passant_position(), 2);
+    check_unmake(&mut promotion_position(), 2);
+}
@@ -0,0 +1,142 @@
+//! Writing moves in algebraic notation and reading them back: every legal move
+//! in a range of positions, and the parts of the notation that only some moves
+//! need.
+
+mod common;
+
+use chess3d::{ Board, Notation, NotationError };
+
+use common::Rng;
+
+fn board(fen: &str) -> Board {
+    Board::from_fen(fen).unwrap()
+}
+
+// the legal move written as `long`, in both forms
+fn forms(b: &Board, long: &str) -> (String, String) {
+    let m = b.parse_move(long).unwrap();
+    (b.format_move(&m, Notation::Long), b.format_move(&m, Notation::Short))
+}
+
+// every legal move reads back as itself from both forms
+fn assert_round_trips(b: &Board) {
+    for m in b.legal_moves(b.side_to_move()) {
+        for &notation in &[Notation::Long, Notation::Short] {
+            let text = b.format_move(&m, notation);
+            assert_eq!(b.parse_move(&text), Ok(m), "{} in {}", text, b.to_fen());
+        }
+    }
+}
+
+#[test]
+fn round_trip() {
+    let mut rng = Rng::new(0x2545_f491_4f6c_dd1d);
+    for start in &[Board::new(), Board::preset("raumschach").unwrap(), Board::preset("flat").unwrap()] {
+        let mut b = *start;
+        for _ in 0..16 {
+            assert_round_trips(&b);
+            let moves = b.legal_moves(b.side_to_move());
+            if moves.is_empty() {
+                break;
+            }
+            b.make_move(&rng.pick(&moves));
+        }
+    }
+}
+
+#[test]
+fn long_and_short_forms() {
+    let b = Board::new();
+    assert_eq!(forms(&b, "Nb1A-b3B"), ("Nb1A-b3B".to_owned(), "Nb3B".to_owned()));
+    assert_eq!(forms(&b, "e2A-e4A"), ("e2A-e4A".to_owned(), "e4A".to_owned()));
+    // a partial starting square, annotations and a needless `x` are all accepted
+    let m = b.parse_move("Nb1A-b3B").unwrap();
+    for text in &["Nb3B", "Nb1b3B", " Nb1A-b3B!? ", "Nb1Axb3B"] {
+        assert_eq!(b.parse_move(text), Ok(m), "{}", text);
+    }
+}
+
+#[test]
+fn disambiguation() {
+    // knights on b1A and d1A can both take on c3A
+    let b = board("4k3/8/8/8/8/2p5/8/1N1NK3 w - 0 1");
+    assert_eq!(forms(&b, "Nb1A-c3A").1, "Nbxc3A");
+    assert_eq!(forms(&b, "Nd1A-c3A").1, "Ndxc3A");
+    assert_eq!(b.parse_move("Nxc3A"), Err(NotationError::Ambiguous("Nxc3A".to_owned())));
+
+    // rooks on one file are told apart by rank
+    let b = board("4k3/8/8/R7/8/8/8/R3K3 w - 0 1");
+    assert_eq!(forms(&b, "Ra1A-a3A").1, "R1a3A");
+    assert_eq!(forms(&b, "Ra5A-a3A").1, "R5a3A");
+
+    // and rooks above each other by layer
+    let b = board("4k3/8/8/8/8/8/8/R3K3|8/8/8/8/8/8/8/8|4k*3/8/8/8/8/8/8/R7 w - 0 1");
+    assert_eq!(forms(&b, "Ra1A-a1B").1, "RAa1B");
+    assert_eq!(forms(&b, "Ra1C-a1B").1, "RCa1B");
+}
+
+#[test]
+fn captures() {
+    let b = board("4k3/8/8/3p4/4P*3/8/8/4K3 w - 0 1");
+    assert_eq!(forms(&b, "e4A-d5A"), ("e4Axd5A".to_owned(), "exd5A".to_owned()));
+    // a pawn capture keeps its file even when nothing else could take
+    assert_eq!(b.parse_move("exd5A"), b.parse_move("e4A-d5A"));
+}
+
+#[test]
+fn en_passant_is_a_capture() {
+    let b = board("4k3/8/8/3p*P*3/8/8/8/4K3 w d6A:d5A 0 2");
+    assert_eq!(forms(&b, "e5A-d6A"), ("e5Axd6A".to_owned(), "exd6A".to_owned()));
+}
+
+#[test]
+fn check_and_checkmate() {
+    let b = board("6k1/5ppp/8/8/8/8/8/R5K1 w - 0 1");
+    assert_eq!(forms(&b, "Ra1A-a8A"), ("Ra1A-a8A#".to_owned(), "Ra8A#".to_owned()));
+    let b = board("6k1/8/8/8/8/8/8/R5K1 w - 0 1");
+    assert_eq!(forms(&b, "Ra1A-a8A"), ("Ra1A-a8A+".to_owned(), "Ra8A+".to_owned()));
+    assert_eq!(forms(&b, "Ra1A-a7A").1, "Ra7A");
+    // the marks are optional when reading
+    assert_eq!(b.parse_move("Ra8A"), b.parse_move("Ra8A+"));
+}
+
+#[test]
+fn promotion() {
+    let b = board("r3k3/1P*6/8/8/8/8/8/4K3 w - 0 1");
+    assert_eq!(forms(&b, "b7A-b8A=Q"), ("b7A-b8A=Q+".to_owned(), "b8A=Q+".to_owned()));
+    assert_eq!(forms(&b, "b7A-a8A=N"), ("b7Axa8A=N".to_owned(), "bxa8A=N".to_owned()));
+    // the piece has to be named
+    assert!(matches!(b.parse_move("b8A"), Err(NotationError::NoSuchMove(_))));
+    assert!(matches!(b.parse_move("b8A=K"), Err(NotationError::Malformed(_))));
+
+    // on the full cube a pawn promotes on the last rank of the top layer
+    let layers = format!("8/8/8/8/8/8/8/4K3|{}|2P*5/8/8/8/8/8/8/8|7k/8/8/8/8/8/8/8", ["8/8/8/8/8/8/8/8"; 5].join("|"));
+    let b = board(&format!("{} w - 0 1", layers));
+    assert_eq!(forms(&b, "c8G-c8H=Q"), ("c8G-c8H=Q+".to_owned(), "c8H=Q+".to_owned()));
+    assert!(matches!(b.parse_move("c8G-c8H"), Err(NotationError::NoSuchMove(_))));
+}
+
+#[test]
+fn castling() {
+    let b = board("r3k2r/8/8/8/8/8/8/R3K2R w - 0 1");
+    assert_eq!(forms(&b, "O-O"), ("O-O".to_owned(), "O-O".to_owned()));
+    assert_eq!(forms(&b, "O-O-O"), ("O-O-O".to_owned(), "O-O-O".to_owned()));
+    // also written with zeros, or as the king's move
+    assert_eq!(b.parse_move("0-0"), b.parse_move("O-O"));
+    assert_eq!(b.parse_move("0-0-0"), b.parse_move("O-O-O"));
+    assert_eq!(b.parse_move("Ke1A-g1A"), b.parse_move("O-O"));
+
+    let b = board("r3k2r/8/8/8/8/8/8/R3K2R b - 0 1");
+    assert_eq!(forms(&b, "O-O-O").0, "O-O-O");
+    // not once the king has moved
+    let b = board("r3k2r/8/8/8/8/8/8/R3K*2R w - 0 1");
+    assert_eq!(b.parse_move("O-O"), Err(NotationError::NoSuchMove("O-O".to_owned())));
+}
+
+#[test]
+fn malformed() {
+    let b = Board::new();
+    for text in &["", "e4", "Ze4A", "e2A-e4A=", "e2Ae4Az", "Nb1A-b3B=QQ", "x"] {
+        assert_eq!(b.parse_move(text), Err(NotationError::Malformed((*text).to_owned())), "{}", text);
+    }
+}
@@ -0,0 +1,156 @@
+//! Perft node counts for the starting position and positions that exercise
+//! castling, en passant, promotion and check evasion in 3D, and for the smaller
+//! board sizes. A single-layer board plays ordinary chess, so its counts are
+//! the well-known ones. A change to any count means move generation changed.
+//! The deeper searches are slow in debug builds and are ignored by default;
+//! run them with `cargo test --release -p chess3d -- --ignored`.
+
+mod common;
+
+use chess3d::{ perft, perft_divide, Board, Colors, Dimensions, Pieces, RuleSet };
+
+use common::{ castling_position, empty_board, en_passant_position, legal_move, place, promotion_position, Square };
+
+use Colors::*;
+use Pieces::*;
+
+// plays the legal move between two squares
+fn play(b: &mut Board, from: Square, to: Square) {
+    let m = legal_move(b, from, to);
+    b.make_move(&m);
+}
+
+// White is in check from a queen on a yz diagonal and has to respond
+fn check_position() -> Board {
+    let mut b = empty_board();
+    place(&mut b, &[
+        ((3, 3, 3), White, King(true)),
+        ((3, 6, 6), Black, Queen),
+        ((0, 3, 0), Black, Knight),
+        ((7, 7, 7), Black, King(true)),
+        ((5, 2, 4), White, Knight),
+        ((1, 1, 1), White, Rook(true)),
+        ((3, 2, 2), White, Pawn(true)),
+    ]);
+    b
+}
+
+// a few opening moves by both sides, including a capture
+fn opening_position() -> Board {
+    let mut b = Board::new();
+    play(&mut b, (4, 1, 0), (4, 3, 0));
+    play(&mut b, (3, 6, 7), (3, 6, 5));
+    play(&mut b, (6, 0, 0), (5, 2, 0));
+    play(&mut b, (3, 7, 7), (3, 1, 1));
+    b
+}
+
+#[test]
+fn start_position() {
+    let b = Board::new();
+    assert_eq!(perft(&b, 0), 1);
+    assert_eq!(perft(&b, 1), 113);
+    assert_eq!(perft(&b, 2), 12559);
+}
+
+#[test]
+fn raumschach_start_position() {
+    let b = Board::with_rules(RuleSet::RAUMSCHACH);
+    assert_eq!(perft(&b, 1), 134);
+    assert_eq!(perft(&b, 2), 16591);
+}
+
+#[test]
+fn classic_chess() {
+    let b = Board::with_dimensions(Dimensions::FLAT, RuleSet::CLASSIC);
+    assert_eq!(perft(&b, 1), 20);
+    assert_eq!(perft(&b, 2), 400);
+    assert_eq!(perft(&b, 3), 8902);
+}
+
+#[test]
+fn small_boards() {
+    let raumschach = Board::with_dimensions(Dimensions::RAUMSCHACH, RuleSet::RAUMSCHACH);
+    assert_eq!(perft(&raumschach, 1), 96);
+    assert_eq!(perft(&raumschach, 2), 8751);
+
+    let training = Board::with_dimensions(Dimensions::TRAINING, RuleSet::CLASSIC);
+    assert_eq!(perft(&training, 1), 37);
+    assert_eq!(perft(&training, 2), 1324);
+    assert_eq!(perft(&training, 3), 51795);
+}
+
+#[test]
+#[ignore]
+fn start_position_deep() {
+    assert_eq!(perft(&Board::new(), 3), 1515278);
+}
+
+#[test]
+#[ignore]
+fn small_boards_deep() {
+    let flat = Board::with_dimensions(Dimensions::FLAT, RuleSet::CLASSIC);
+    assert_eq!(perft(&flat, 5), 4865609);
+    let raumschach = Board::with_dimensions(Dimensions::RAUMSCHACH, RuleSet::RAUMSCHACH);
+    assert_eq!(perft(&raumschach, 3), 852163);
+    let training = Board::with_dimensions(Dimensions::TRAINING, RuleSet::CLASSIC);
+    assert_eq!(perft(&training, 4), 1978885);
+}
+
+#[test]
+#[ignore]
+fn tricky_positions_deep() {
+    assert_eq!(perft(&castling_position(), 3), 214694);
+    assert_eq!(perft(&en_passant_position(), 4), 100026);
+    assert_eq!(perft(&promotion_position(), 4), 2968420);
+    assert_eq!(perft(&check_position(), 4), 3629030);
+    assert_eq!(perft(&opening_position(), 3), 1901137);
+}
+
+#[test]
+fn divide_sums_to_perft() {
+    let b = opening_position();
+    let divide = perft_divide(&b, 2);
+    assert_eq!(divide.len() as u64, perft(&b, 1));
+    assert_eq!(divide.iter().map(|(_, n)| n).sum::<u64>(), perft(&b, 2));
+}
+
+#[test]
+fn castling() {
+    let b = castling_position();
+    assert_eq!(perft(&b, 1), 59);
+    assert_eq!(perft(&b, 2), 3481);
+}
+
+#[test]
+fn en_passant() {
+    let b = en_passant_position();
+    assert_eq!(perft(&b, 1), 18);
+    assert_eq!(perft(&b, 2), 292);
+    assert_eq!(perft(&b, 3), 5635);
+}
+
+#[test]
+fn promotion() {
+    let b = promotion_position();
+    assert_eq!(perft(&b, 1), 44);
+    assert_eq!(perft(&b, 2), 1572);
+    assert_eq!(perft(&b, 3), 76105);
+}
+
+#[test]
+fn check_evasion() {
+    let b = check_position();
+    assert!(b.is_in_check(White));
+    assert_eq!(perft(&b, 1), 16);
+    assert_eq!(perft(&b, 2), 992);
+    assert_eq!(perft(&b, 3), 56721);
+}
+
+#[test]
+fn opening() {
+    let b = opening_position();
+    assert_eq!(perft(&b, 1), 112);
+    assert_eq!(perft(&b, 2), 16149);
+}
+
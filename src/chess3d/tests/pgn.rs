@@ -0,0 +1,210 @@
+//! Reading game records, with their tags, comments, annotations and
+//! variations, and writing them back out.
+
+use chess3d::{ Board, Colors, Game, GameRecord, GameResult, Notation, NotationError, PgnError, RecordMove };
+
+// the example in the `pgn` module's documentation
+const EXAMPLE: &str = r#"[Event "Casual game"]
+[Result "*"]
+[Rules "classic"]
+
+1. e4A {a quiet start} e5H 2. Nc3A $1 (2. Nb3B Nc6H) Nf6H *
+"#;
+
+// the short form of each move of a line played from `board`
+fn line(moves: &[RecordMove], mut board: Board) -> Vec<String> {
+    moves.iter()
+        .map(|m| {
+            let text = board.format_move(&m.r#move, Notation::Short);
+            board.make_move(&m.r#move);
+            text
+        })
+        .collect()
+}
+
+fn parse(text: &str) -> GameRecord {
+    GameRecord::parse(text).unwrap()
+}
+
+#[test]
+fn documentation_example() {
+    let record = parse(EXAMPLE);
+    assert_eq!(record.tag("Event"), Some("Casual game"));
+    assert_eq!(record.tag("Rules"), Some("classic"));
+    let start = record.start_board().unwrap();
+    assert_eq!(line(&record.moves, start), ["e4A", "e5H", "Nc3A", "Nf6H"]);
+    assert_eq!(record.moves[0].comment.as_deref(), Some("a quiet start"));
+    assert_eq!(record.moves[2].nags, [1]);
+
+    // the variation replaces 2. Nc3A, so it starts after 1... e5H
+    let mut before = start;
+    for m in &record.moves[..2] {
+        before.make_move(&m.r#move);
+    }
+    assert_eq!(record.moves[2].variations.len(), 1);
+    assert_eq!(line(&record.moves[2].variations[0], before), ["Nb3B", "Nc6H"]);
+
+    let game = record.to_game().unwrap();
+    assert_eq!(game.moves().len(), 4);
+    assert_eq!(game.result(), None);
+}
+
+#[test]
+fn tag_escaping() {
+    let mut record = parse(EXAMPLE);
+    record.set_tag("White", r#"Nolan "the \ knight""#);
+    let text = record.to_pgn().unwrap();
+    assert!(text.contains(r#"[White "Nolan \"the \\ knight\""]"#), "{}", text);
+    assert_eq!(parse(&text), record);
+
+    // set_tag replaces in place
+    record.set_tag("Event", "Rematch");
+    assert_eq!(record.tags[0], ("Event".to_owned(), "Rematch".to_owned()));
+
+    for text in &["[White \"unterminated]\n*", "[\"no name\"]\n*", "[White \"x\"\n*"] {
+        assert!(matches!(GameRecord::parse(text), Err(PgnError::MalformedTag { line: 1 })), "{}", text);
+    }
+}
+
+#[test]
+fn comments() {
+    let record = parse("{before   the\n  first move} 1. e4A ; to the end of the line\ne5H {one} {two} *");
+    assert_eq!(record.moves[0].comment_before.as_deref(), Some("before the first move"));
+    assert_eq!(record.moves[0].comment.as_deref(), Some("to the end of the line"));
+    assert_eq!(record.moves[1].comment_before, None);
+    assert_eq!(record.moves[1].comment.as_deref(), Some("one two"));
+    assert_eq!(line(&record.moves, Board::new()), ["e4A", "e5H"]);
+
+    assert_eq!(
+        GameRecord::parse("1. e4A\n{never closed *"),
+        Err(PgnError::UnterminatedComment { line: 2 }),
+    );
+}
+
+#[test]
+fn closing_braces_in_comments() {
+    let mut record = parse("1. e4A e5H *");
+    record.moves[0].comment_before = Some("open {and} shut".to_owned());
+    record.moves[1].comment = Some("}".to_owned());
+    let text = record.to_pgn().unwrap();
+    assert!(text.contains("{open {and) shut} 1. e4A e5H {)} *"), "{}", text);
+
+    let read = parse(&text);
+    assert_eq!(read.moves[0].comment_before.as_deref(), Some("open {and) shut"));
+    assert_eq!(read.moves[1].comment.as_deref(), Some(")"));
+    assert_eq!(parse(&read.to_pgn().unwrap()), read);
+}
+
+#[test]
+fn annotations() {
+    let record = parse("1. e4A! e5H? 2. Nc3A!! Nc6H?? 3. Nh3A!? Nf6H?! 4. d3A $14 $200 *");
+    let nags: Vec<&[u8]> = record.moves.iter().map(|m| m.nags.as_slice()).collect();
+    assert_eq!(nags, [&[1][..], &[2], &[3], &[4], &[5], &[6], &[14, 200]]);
+    assert_eq!(line(&record.moves, Board::new())[4], "Nh3A");
+
+    assert!(matches!(GameRecord::parse("$1 1. e4A *"), Err(PgnError::UnexpectedToken { .. })));
+}
+
+#[test]
+fn nested_variations() {
+    let record = parse("1. e4A (1. d4A d5H (1... e5H 2. c3A) 2. c4A) (1. Nb3B) e5H *");
+    let start = Board::new();
+    assert_eq!(line(&record.moves, start), ["e4A", "e5H"]);
+
+    let variations = &record.moves[0].variations;
+    assert_eq!(variations.len(), 2);
+    assert_eq!(line(&variations[0], start), ["d4A", "d5H", "c4A"]);
+    assert_eq!(line(&variations[1], start), ["Nb3B"]);
+
+    // the inner variation replaces 1... d5H
+    let mut after_d4 = start;
+    after_d4.make_move(&variations[0][0].r#move);
+    let inner = &variations[0][1].variations;
+    assert_eq!(inner.len(), 1);
+    assert_eq!(line(&inner[0], after_d4), ["e5H", "c3A"]);
+
+    // written back out and read again, nothing is lost
+    assert_eq!(parse(&record.to_pgn().unwrap()), record);
+
+    for text in &["1. e4A (1. d4A *", "1. e4A ) *", "(1. e4A) *"] {
+        assert!(GameRecord::parse(text).is_err(), "{}", text);
+    }
+    assert!(matches!(GameRecord::parse("1. e4A (1. d4A"), Err(PgnError::UnbalancedVariation { .. })));
+}
+
+#[test]
+fn several_games() {
+    let text = format!(
+        "{}\n[Event \"Second\"]\n\n1. e4A e5H 1-0\n\n[Event \"Third\"]\n1. d4A\n[Event \"Fourth\"]\n*\n",
+        EXAMPLE,
+    );
+    let records = GameRecord::parse_all(&text).unwrap();
+    let events: Vec<_> = records.iter().map(|r| r.tag("Event").unwrap()).collect();
+    assert_eq!(events, ["Casual game", "Second", "Third", "Fourth"]);
+    // the termination marker stands in for a missing Result tag
+    assert_eq!(records[1].tag("Result"), Some("1-0"));
+    // a game may end without one when the next game's tags follow
+    assert_eq!(records[2].tag("Result"), None);
+    assert_eq!(records[2].moves.len(), 1);
+    assert!(records[3].moves.is_empty());
+
+    assert_eq!(GameRecord::parse(&text).unwrap(), records[0]);
+    assert_eq!(GameRecord::parse_all("  \n").unwrap(), []);
+    assert_eq!(GameRecord::parse(""), Err(PgnError::NoGame));
+}
+
+#[test]
+fn illegal_moves() {
+    match GameRecord::parse("1. e4A e5H\n2. e6A *") {
+        Err(PgnError::IllegalMove { line, text, error }) => {
+            assert_eq!((line, text.as_str()), (2, "e6A"));
+            assert_eq!(error, NotationError::NoSuchMove("e6A".to_owned()));
+        },
+        other => panic!("expected an illegal move, got {:?}", other),
+    }
+}
+
+#[test]
+fn from_game_round_trip() {
+    let mut game = Game::new();
+    for text in &["e4A", "e5H", "Nc3A", "Nc6H", "Nb5A", "d6H", "Nxc7A+"] {
+        let m = game.board().parse_move(text).unwrap();
+        game.play(&m).unwrap();
+    }
+    let record = GameRecord::from_game(&game);
+    assert_eq!(record.tag("Result"), Some("*"));
+    assert_eq!(record.tag("FEN"), None);
+
+    let read = parse(&record.to_pgn().unwrap());
+    assert_eq!(read, record);
+    assert_eq!(read.to_game().unwrap().moves(), game.moves());
+    assert_eq!(read.to_game().unwrap().board().to_fen(), game.board().to_fen());
+}
+
+#[test]
+fn writing_needs_a_valid_start() {
+    let mut record = parse(EXAMPLE);
+    record.set_tag("FEN", "not a position");
+    assert!(matches!(record.to_pgn(), Err(PgnError::InvalidFen(_))));
+    record.set_tag("FEN", &Board::new().to_fen());
+    record.set_tag("Rules", "nonsense");
+    assert_eq!(record.to_pgn(), Err(PgnError::InvalidRules("nonsense".to_owned())));
+}
+
+#[test]
+fn from_game_with_a_setup_and_result() {
+    let start = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - 0 1").unwrap();
+    let mut game = Game::from_board(start);
+    let m = game.board().parse_move("Ra8A#").unwrap();
+    game.play(&m).unwrap();
+
+    let record = GameRecord::from_game(&game);
+    assert_eq!(record.tag("Result"), Some("1-0"));
+    assert_eq!(record.tag("SetUp"), Some("1"));
+    assert_eq!(record.tag("FEN"), Some(start.to_fen().as_str()));
+    let text = record.to_pgn().unwrap();
+    assert!(text.contains("1. Ra8A# 1-0"), "{}", text);
+
+    let replayed = parse(&text).to_game().unwrap();
+    assert_eq!(replayed.result(), Some(GameResult::Checkmate { winner: Colors::White }));
+}
@@ -1,7 +1,16 @@
-use chess3d::Board;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use chess3d::{ Board, Colors, Dimensions, Game, GameRecord, Move, PgnError };
+use chess3d_engine::{ Engine, SearchLimits };
+
+use cursive::{ CbSink, Cursive };
+use cursive::view::{ Nameable, Resizable };
 
 use cursive::Printer;
-use cursive::views::Dialog;
+use cursive::views::{ Dialog, EditView, RadioGroup, TextView };
 use cursive::views::Panel;
 use cursive::views::LinearLayout;
 use cursive::Vec2;
@@ -10,23 +19,219 @@ use cursive::theme::Color;
 use cursive::theme::BaseColor;
 use cursive::theme::ColorStyle;
 
+// the computer takes at least this long so its moves can be followed
+const MIN_THINKING_TIME: Duration = Duration::from_millis(400);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    fn limits(self) -> SearchLimits {
+        match self {
+            Difficulty::Easy => SearchLimits::depth(1),
+            Difficulty::Medium => SearchLimits { depth: Some(3), time: Some(Duration::from_secs(2)), ..SearchLimits::default() },
+            Difficulty::Hard => SearchLimits::time(Duration::from_secs(5)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Player {
+    Human,
+    Computer(Difficulty),
+}
+
 struct BoardView {
-    chess_board: Board,
+    game: Game,
     cursor: Option<(isize, isize, isize)>,
-    current_moves: Option<Vec<chess3d::Move>>,
+    current_moves: Option<Vec<Move>>,
+    white: Player,
+    black: Player,
+    // computer moves are sent back through this from the searching thread
+    cb_sink: CbSink,
+    // set while the computer is thinking; storing true abandons the search
+    thinking: Option<Arc<AtomicBool>>,
+    // counts searches started, so a move from an abandoned one is ignored
+    searches: u64,
+    // one engine for each computer player, kept between moves for its hash
+    // table; taken by the searching thread and handed back with its move
+    white_engine: Option<Engine>,
+    black_engine: Option<Engine>,
 }
 
 impl BoardView {
-    pub fn new() -> BoardView {
+    pub fn new(game: Game, white: Player, black: Player, cb_sink: CbSink) -> BoardView {
         BoardView {
-            chess_board: Board::new(),
+            game,
             cursor: None,
             current_moves: None,
+            white,
+            black,
+            cb_sink,
+            thinking: None,
+            searches: 0,
+            white_engine: engine_for(white),
+            black_engine: engine_for(black),
+        }
+    }
+}
+
+fn engine_for(player: Player) -> Option<Engine> {
+    match player {
+        Player::Computer(_) => Some(Engine::new()),
+        Player::Human => None,
+    }
+}
+
+impl BoardView {
+    fn player(&self, c: Colors) -> Player {
+        match c {
+            Colors::White => self.white,
+            Colors::Black => self.black,
+        }
+    }
+
+    fn engine(&mut self, c: Colors) -> &mut Option<Engine> {
+        match c {
+            Colors::White => &mut self.white_engine,
+            Colors::Black => &mut self.black_engine,
+        }
+    }
+
+    fn humans_turn(&self) -> bool {
+        self.player(self.game.board().side_to_move()) == Player::Human
+    }
+
+    // plays the move, returning a message to show the player if there is one
+    fn play_move(&mut self, m: &Move) -> Option<String> {
+        if let Err(e) = self.game.play(m) {
+            return Some(format!("Move rejected: {}", e));
+        }
+        self.start_turn();
+        self.game.result().map(|result| result.to_string())
+    }
+
+    // plays a move typed in algebraic notation
+    fn play_notation(&mut self, text: &str) -> Option<String> {
+        if !self.humans_turn() {
+            return Some("Wait for the computer to move".to_owned());
+        }
+        match self.game.board().parse_move(text) {
+            Ok(m) => {
+                self.cursor = None;
+                self.current_moves = None;
+                self.play_move(&m)
+            },
+            Err(e) => Some(format!("Move rejected: {}", e)),
+        }
+    }
+
+    // sets the computer thinking if it is to move; if its engine is still
+    // out with an abandoned search, that starts once the engine comes back
+    fn start_turn(&mut self) {
+        let color = self.game.board().side_to_move();
+        let difficulty = match self.player(color) {
+            Player::Computer(d) => d,
+            Player::Human => return,
+        };
+        if self.game.result().is_some() || self.thinking.is_some() {
+            return;
         }
+        let mut engine = match self.engine(color).take() {
+            Some(engine) => engine,
+            None => return,
+        };
+        self.thinking = Some(engine.stop_flag());
+        self.searches += 1;
+        let search = self.searches;
+        let board = *self.game.board();
+        let sink = self.cb_sink.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            let result = engine.search(&board, difficulty.limits(), |_| {});
+            if let Some(rest) = MIN_THINKING_TIME.checked_sub(start.elapsed()) {
+                thread::sleep(rest);
+            }
+            // fails only once the interface has closed
+            let _ = sink.send(Box::new(move |s: &mut Cursive| {
+                let text = s
+                    .call_on_name("board", |v: &mut BoardView| v.computer_move(search, color, engine, result.best_move))
+                    .flatten();
+                if let Some(text) = text {
+                    s.add_layer(Dialog::info(text));
+                }
+            }));
+        });
+    }
+
+    fn computer_move(&mut self, search: u64, color: Colors, engine: Engine, m: Option<Move>) -> Option<String> {
+        *self.engine(color) = Some(engine);
+        if search != self.searches {
+            // the search was abandoned, and the turn may have been waiting for
+            // this engine to come back
+            self.start_turn();
+            return None;
+        }
+        self.thinking = None;
+        m.and_then(|m| self.play_move(&m))
+    }
+
+    // abandons any search and takes back moves until it is a human's turn, or
+    // one move if the computer plays both sides
+    fn take_back(&mut self) {
+        if let Some(stop) = self.thinking.take() {
+            stop.store(true, Ordering::Relaxed);
+            self.searches += 1;
+        }
+        let has_human = self.white == Player::Human || self.black == Player::Human;
+        while self.game.undo().is_some() {
+            if self.humans_turn() || !has_human {
+                break;
+            }
+        }
+        self.cursor = None;
+        self.current_moves = None;
+        self.start_turn();
     }
 }
 
+fn submit_move(siv: &mut Cursive, text: &str) {
+    let message = siv.call_on_name("board", |v: &mut BoardView| v.play_notation(text)).flatten();
+    siv.call_on_name("move", |v: &mut EditView| v.set_content(""));
+    if let Some(text) = message {
+        siv.add_layer(Dialog::info(text));
+    }
+}
+
+fn show_promotion_dialog(siv: &mut Cursive, candidates: &[Move]) {
+    let mut dialog = Dialog::text("Choose a piece").title("Promote Pawn");
+    for &m in candidates {
+        let label = match m.promotion() {
+            Some(p) => format!("{:?}", p),
+            None => continue,
+        };
+        dialog.add_button(label, move |s| {
+            s.pop_layer();
+            let text = s.call_on_name("board", |v: &mut BoardView| v.play_move(&m)).flatten();
+            if let Some(text) = text {
+                s.add_layer(Dialog::info(text));
+            }
+        });
+    }
+    siv.add_layer(dialog);
+}
+
+// the columns taken by one layer: two per file plus the frame and a gap
+fn layer_stride(files: usize) -> usize {
+    files * 2 + 3
+}
+
 impl cursive::view::View for BoardView {
+    #[allow(clippy::needless_range_loop)]
     fn draw(&self, printer: &Printer) {
         let mut bg_colors = [[[Color::Dark(BaseColor::White); 8]; 8]; 8];
 
@@ -56,31 +261,33 @@ impl cursive::view::View for BoardView {
             },
         };
         
-        for z in 0..8 {
-            printer.print_box((19 * z + 1 as usize, 1 as usize), (18, 10), false);
+        let dims = self.game.board().dimensions();
+        let (files, ranks) = (dims.files() as usize, dims.ranks() as usize);
+        let stride = layer_stride(files);
+        for z in 0..dims.layers() as usize {
+            printer.print_box((stride * z + 1, 1), (stride - 1, ranks + 2), false);
 
-            for y in (0..8).rev() {
-                for x in 0..8 {
-                    let tile = self.chess_board.at((x as isize, y as isize, z as isize));
+            for y in (0..ranks).rev() {
+                for x in 0..files {
+                    let tile = self.game.board().at((x as isize, y as isize, z as isize));
 
                     use chess3d::BoardState;
                     use chess3d::Colors;
                     let mut text: String = " ".to_owned();
-                    let color;
-                    match tile {
+                    let color = match tile {
                         BoardState::Empty => { 
-                            text.push_str(" ");
-                            color = Color::Dark(BaseColor::White);
+                            text.push(' ');
+                            Color::Dark(BaseColor::White)
                         },
                         BoardState::Piece((Colors::Black, p)) => {
                             text.push(p.character());
-                            color = Color::RgbLowRes(0, 0, 0);
+                            Color::RgbLowRes(0, 0, 0)
                         },
                         BoardState::Piece((Colors::White, p)) => {
                             text.push(p.character());
-                            color = Color::Rgb(255, 255, 255);
+                            Color::Rgb(255, 255, 255)
                         },
-                    }
+                    };
                     let bg_color = match self.cursor {
                         None => Color::Dark(BaseColor::White),
                         Some(_) => {
@@ -90,7 +297,7 @@ impl cursive::view::View for BoardView {
 
                     printer.with_color(
                         ColorStyle::new(color, bg_color),
-                        |printer| printer.print(((x * 2) + (19 * z + 2), y + 2), &text)
+                        |printer| printer.print(((x * 2) + (stride * z + 2), y + 2), &text)
                     );
                 }
             }
@@ -98,38 +305,51 @@ impl cursive::view::View for BoardView {
     }
 
     fn required_size(&mut self, _: Vec2) -> Vec2 {
+        let dims = self.game.board().dimensions();
         Vec2 {
-            x: (19)*8 + 1,
-            y: 12,
+            x: layer_stride(dims.files() as usize) * dims.layers() as usize + 1,
+            y: dims.ranks() as usize + 4,
         }
     }
 
     fn on_event(&mut self, event: Event) -> EventResult {
         use cursive::XY;
         match event {
+            Event::Mouse { .. } if !self.humans_turn() => EventResult::Ignored,
             Event::Mouse {offset, position, event: MouseEvent::Release(MouseButton::Left)} => {
                 let mut pos: XY<isize> = (position.x as isize, position.y as isize).into();
                 let off: XY<isize> = (offset.x as isize, offset.y as isize).into();
                 pos = pos - off;
-                let board = (pos.x - 2) / 19;
-                pos.x -= 19 * board;
+                let dims = self.game.board().dimensions();
+                let stride = layer_stride(dims.files() as usize) as isize;
+                let board = (pos.x - 2) / stride;
+                pos.x -= stride * board;
                 pos = pos - (3, 2);
                 pos.x = (pos.x + 1) / 2;
-                if pos.x >= 0 && pos.x <= 7 && pos.y >= 0 && pos.y <= 7 {
+                if dims.contains((pos.x, pos.y, board).into()) {
                     // valid position
                     if let Some(moves) = &self.current_moves {
-                        let m = moves.iter().find(|i| i.to() == (pos.x, pos.y, board).into());
-                        if let Some(selected_move) = m {
-                            self.chess_board.execute_move(selected_move);
+                        let candidates: Vec<Move> = moves.iter()
+                            .filter(|i| i.to() == (pos.x, pos.y, board).into())
+                            .cloned()
+                            .collect();
+                        if !candidates.is_empty() {
                             self.cursor = None;
                             self.current_moves = None;
+                            if candidates.len() > 1 {
+                                // only promotions share a destination square
+                                return EventResult::with_cb(move |s| show_promotion_dialog(s, &candidates));
+                            }
+                            if let Some(text) = self.play_move(&candidates[0]) {
+                                return EventResult::with_cb(move |s| { s.add_layer(Dialog::info(text.clone())); });
+                            }
                         } else {
                             self.cursor = Some((pos.x, pos.y, board));
-                            self.current_moves = Some(self.chess_board.piece_moves((pos.x, pos.y, board).into()));
+                            self.current_moves = Some(self.game.board().legal_piece_moves((pos.x, pos.y, board).into()));
                         }
                     } else {
                         self.cursor = Some((pos.x, pos.y, board));
-                        self.current_moves = Some(self.chess_board.piece_moves((pos.x, pos.y, board).into()));
+                        self.current_moves = Some(self.game.board().legal_piece_moves((pos.x, pos.y, board).into()));
                     }
                     EventResult::Consumed(None)
                 } else {
@@ -142,21 +362,126 @@ impl cursive::view::View for BoardView {
     
 }
 
-fn main() {
-    // let b: Board = Board::new();
+fn show_start_menu(siv: &mut Cursive) {
+    siv.add_layer(
+        Dialog::text("Who is playing?")
+            .title("3D Chess")
+            .button("Human vs Human", |s| start_game(s, Player::Human, Player::Human))
+            .button("Human vs Computer", show_opponent_menu)
+            .button("Computer vs Computer", show_computer_match_menu)
+    );
+}
 
-    // println!("{}", b);
+fn difficulty_buttons(title: &str, group: &mut RadioGroup<Difficulty>) -> Panel<LinearLayout> {
+    let mut buttons = LinearLayout::vertical();
+    for &d in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard].iter() {
+        buttons.add_child(group.button(d, format!("{:?}", d)));
+    }
+    Panel::new(buttons).title(title)
+}
 
-    // println!("{:?}", b.piece_moves(Location::new(0, 1, 0)));
-    let mut siv = cursive::default();
-    siv.add_global_callback('q', |s| s.quit());
+fn show_opponent_menu(siv: &mut Cursive) {
+    let mut colour = RadioGroup::new();
+    let mut difficulty = RadioGroup::new();
+    let colours = LinearLayout::vertical()
+        .child(colour.button(Colors::White, "White"))
+        .child(colour.button(Colors::Black, "Black"));
+    siv.pop_layer();
+    siv.add_layer(
+        Dialog::around(
+            LinearLayout::horizontal()
+                .child(Panel::new(colours).title("Play as"))
+                .child(difficulty_buttons("Computer", &mut difficulty)),
+        )
+            .title("Human vs Computer")
+            .button("Start", move |s| {
+                let computer = Player::Computer(*difficulty.selection());
+                match *colour.selection() {
+                    Colors::White => start_game(s, Player::Human, computer),
+                    Colors::Black => start_game(s, computer, Player::Human),
+                }
+            })
+    );
+}
+
+fn show_computer_match_menu(siv: &mut Cursive) {
+    let mut white = RadioGroup::new();
+    let mut black = RadioGroup::new();
+    siv.pop_layer();
+    siv.add_layer(
+        Dialog::around(
+            LinearLayout::horizontal()
+                .child(difficulty_buttons("White", &mut white))
+                .child(difficulty_buttons("Black", &mut black)),
+        )
+            .title("Computer vs Computer")
+            .button("Start", move |s| {
+                start_game(s, Player::Computer(*white.selection()), Player::Computer(*black.selection()));
+            })
+    );
+}
+
+fn start_game(siv: &mut Cursive, white: Player, black: Player) {
+    let game = match siv.take_user_data::<Game>() {
+        Some(game) => game,
+        None => return,
+    };
+    let board = BoardView::new(game, white, black, siv.cb_sink().clone());
+    siv.pop_layer();
     siv.add_layer(
         Dialog::new()
             .title("3D Chess")
             .content(
-                LinearLayout::horizontal()
-                    .child(Panel::new(BoardView::new())),
+                LinearLayout::vertical()
+                    .child(Panel::new(board.with_name("board")))
+                    .child(LinearLayout::horizontal()
+                        .child(TextView::new("Move: "))
+                        .child(EditView::new().on_submit(submit_move).with_name("move").fixed_width(20))),
             )
+            .button("Take Back", |s| {
+                s.call_on_name("board", |v: &mut BoardView| v.take_back());
+            })
     );
+    // the computer may have the first move
+    siv.call_on_name("board", |v: &mut BoardView| v.start_turn());
+}
+
+fn load_game(path: &str) -> Result<Game, Box<dyn std::error::Error>> {
+    let text = std::fs::read_to_string(path)?;
+    let record = GameRecord::parse_all(&text)?.pop().ok_or(PgnError::NoGame)?;
+    Ok(record.to_game()?)
+}
+
+fn main() {
+    // let b: Board = Board::new();
+
+    // println!("{}", b);
+
+    // println!("{:?}", b.piece_moves(Location::new(0, 1, 0)));
+    // a board size preset, or a game record to carry on from the last game in
+    let game = match std::env::args().nth(1) {
+        Some(path) if path.ends_with(".pgn") => match load_game(&path) {
+            Ok(game) => game,
+            Err(e) => {
+                eprintln!("Could not load {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        Some(name) => match Board::preset(&name) {
+            Some(board) => Game::from_board(board),
+            None => {
+                let names: Vec<&str> = Dimensions::PRESETS.iter().map(|&(n, _)| n).collect();
+                eprintln!("Unknown board size {}, expected one of: {}", name, names.join(", "));
+                std::process::exit(1);
+            }
+        },
+        None => Game::new(),
+    };
+
+    let mut siv = cursive::default();
+    siv.add_global_callback('q', |s| s.quit());
+    // the game waits here while the players are chosen
+    siv.set_user_data(game);
+    show_start_menu(&mut siv);
     siv.run();
 }